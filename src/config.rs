@@ -0,0 +1,589 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::{Table, Value};
+
+use crate::{
+    sinks::SinkConfig,
+    sniff::{ScanOptions, DEFAULT_SCAN_RATE, MAX_SCAN_INTERVAL},
+    structs::{
+        net::{IpAddr, IpRange, MacAddr},
+        queue::{self, Overflow},
+    },
+};
+
+/**
+Settings read from toml files, every section is optional:
+
+[scan]
+ranges = ["192.168.1.0/24"]
+rate = 10.0
+burst = 5
+interval = 600
+jitter = 0.2
+adaptive = true
+min_rate = 1.0
+
+[capture]
+queue = 10000
+overflow = "drop-oldest"
+sample = 10
+
+[history]
+changes = 1000
+alerts = 1000
+probes = 100
+
+[[protected]]
+ip = "192.168.1.1"
+mac = "aa:bb:cc:dd:ee:ff"
+restore = true
+
+[defense]
+frames = 3
+min_interval = 30
+
+[aliases]
+"aa:bb:cc:dd:ee:ff" = "gateway"
+
+[[sinks]]
+type = "log"
+
+[[sinks]]
+type = "syslog"
+transport = "udp"
+address = "siem.lan:514"
+classes = ["alert"]
+
+[[sinks]]
+type = "desktop"
+min_interval = 60
+classes = ["changed", "gratuitous-conflict", "protected-host-changed"]
+
+[[sinks]]
+type = "webhook"
+url = "https://chat.lan/hooks/xxx"
+format = "mattermost"
+classes = ["alert"]
+
+[[sinks]]
+type = "email"
+server = "smtp.lan"
+username = "arp-watch"
+password = "secret"
+from = "arp-watch@lan"
+to = ["netadmin@lan"]
+digest = 300
+classes = ["changed-ethernet-address", "flip-flop"]
+
+[[sinks]]
+type = "hook"
+command = "/usr/local/bin/shutdown-port"
+timeout = 10
+concurrency = 4
+classes = ["protected-host-changed"]
+
+[ui]
+theme = "dark"
+
+[metrics]
+listen = "127.0.0.1:9108"
+
+[control]
+socket = "/run/arp-watch.sock"
+
+The system-wide file is read first, the per-user one overrides it key by key.
+*/
+pub const SYSTEM_CONFIG: &str = "/etc/arp-watch/config.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scan: ScanConfig,
+    pub capture: CaptureConfig,
+    pub history: HistoryConfig,
+    pub protected: Vec<ProtectedHost>,
+    pub defense: DefenseConfig,
+    pub aliases: HashMap<MacAddr, String>,
+    pub sinks: Vec<SinkConfig>,
+    pub ui: UiConfig,
+    pub metrics: MetricsConfig,
+    pub control: ControlConfig,
+    // File the config was loaded from, None for the default locations
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    pub ranges: Vec<IpRange>,
+    pub rate: Option<f64>,
+    pub burst: Option<u32>,
+    // Seconds between two sweeps
+    pub interval: Option<u64>,
+    pub jitter: Option<f64>,
+    pub adaptive: Option<bool>,
+    pub min_rate: Option<f64>,
+}
+
+/// Queue between the capture threads and the processing of packets
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    // Packets waiting at most
+    pub queue: usize,
+    // What to drop once the queue is full
+    pub overflow: Overflow,
+    // With the sample policy, one packet out of `sample` is kept
+    pub sample: u64,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> CaptureConfig {
+        CaptureConfig {
+            queue: queue::DEFAULT_CAPACITY,
+            overflow: Overflow::default(),
+            sample: queue::DEFAULT_SAMPLE,
+        }
+    }
+}
+
+/// Entries kept in the lists of changes, alerts and probe results, the oldest go first
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub changes: usize,
+    pub alerts: usize,
+    pub probes: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            changes: 1000,
+            alerts: 1000,
+            probes: 100,
+        }
+    }
+}
+
+/// Host whose binding must never change, the mac is the one first seen if not given
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProtectedHost {
+    pub ip: IpAddr,
+    pub mac: Option<MacAddr>,
+    // Announce the mac again when another one claims the ip, requires the mac
+    #[serde(default)]
+    pub restore: bool,
+}
+
+/// Corrective ARP sent for protected hosts with `restore`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DefenseConfig {
+    // Gratuitous replies sent per correction, a second apart
+    pub frames: u32,
+    // Seconds between two corrections of the same host
+    pub min_interval: u64,
+}
+
+impl Default for DefenseConfig {
+    fn default() -> DefenseConfig {
+        DefenseConfig {
+            frames: 3,
+            min_interval: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    pub theme: Theme,
+}
+
+/// Prometheus endpoint, disabled unless an address is given
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: Option<String>,
+}
+
+/// Unix socket to query and drive a running instance, disabled unless a path is given
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+fn keep<T: PartialEq + Clone>(
+    name: &'static str,
+    section: &mut T,
+    running: &T,
+    changed: &mut Vec<&'static str>,
+) {
+    if section != running {
+        changed.push(name);
+        *section = running.clone();
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub cause: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid config {}: {}", self.path.display(), self.cause)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Loads the given file, or merges the system-wide and per-user files when None
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let paths = match path {
+            Some(path) => vec![path.to_owned()],
+            None => default_paths()
+                .into_iter()
+                .filter(|path| path.exists())
+                .collect(),
+        };
+
+        let mut sources = vec![];
+        for path in paths {
+            let content = fs::read_to_string(&path).map_err(|e| ConfigError {
+                path: path.clone(),
+                cause: e.to_string(),
+            })?;
+            sources.push((path, content));
+        }
+        let mut config = Config::parse(&sources)?;
+        config.source = path.map(Path::to_owned);
+        Ok(config)
+    }
+
+    /// Merges the contents of the files, the later ones override the earlier ones
+    fn parse(sources: &[(PathBuf, String)]) -> Result<Config, ConfigError> {
+        let mut merged = Table::new();
+        for (path, content) in sources.iter() {
+            // Deserializing each file alone points errors to the offending key and line
+            toml::from_str::<Config>(content).map_err(|e| ConfigError {
+                path: path.clone(),
+                cause: e.to_string(),
+            })?;
+            let table: Table = content.parse().map_err(|e: toml::de::Error| ConfigError {
+                path: path.clone(),
+                cause: e.to_string(),
+            })?;
+            merge(&mut merged, table);
+        }
+
+        let last = sources
+            .last()
+            .map(|(path, _)| path.clone())
+            .unwrap_or_default();
+        let config = Config::deserialize(merged).map_err(|e| ConfigError {
+            path: last.clone(),
+            cause: e.to_string(),
+        })?;
+        config
+            .validate()
+            .map_err(|cause| ConfigError { path: last, cause })?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(rate) = self.scan.rate {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(format!("scan.rate: must be positive, got {}", rate));
+            }
+        }
+        if let Some(rate) = self.scan.min_rate {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(format!("scan.min_rate: must be positive, got {}", rate));
+            }
+        }
+        if let Some(interval) = self.scan.interval {
+            if interval > MAX_SCAN_INTERVAL.as_secs() {
+                return Err(format!(
+                    "scan.interval: must be at most {} seconds, got {}",
+                    MAX_SCAN_INTERVAL.as_secs(),
+                    interval
+                ));
+            }
+        }
+        if self.scan.burst == Some(0) {
+            return Err("scan.burst: must be positive".to_owned());
+        }
+        if let Some(jitter) = self.scan.jitter {
+            if !(0.0..=1.0).contains(&jitter) {
+                return Err(format!(
+                    "scan.jitter: must be between 0 and 1, got {}",
+                    jitter
+                ));
+            }
+        }
+        if let Some(host) = self
+            .protected
+            .iter()
+            .find(|host| host.restore && host.mac.is_none())
+        {
+            return Err(format!("protected {}: restore requires the mac", host.ip));
+        }
+        if self.defense.frames == 0 {
+            return Err("defense.frames: must be positive".to_owned());
+        }
+        // Without it, every frame of a hijacker would trigger a correction
+        if self.defense.min_interval == 0 {
+            return Err("defense.min_interval: must be positive".to_owned());
+        }
+        if self.capture.queue == 0 {
+            return Err("capture.queue: must be positive".to_owned());
+        }
+        if self.capture.sample == 0 {
+            return Err("capture.sample: must be positive".to_owned());
+        }
+        let history = &self.history;
+        if history.changes == 0 || history.alerts == 0 || history.probes == 0 {
+            return Err("history: sizes must be positive".to_owned());
+        }
+        for sink in self.sinks.iter() {
+            sink.validate().map_err(|e| format!("sinks: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Puts back the sections of the running config only read at startup,
+    /// returns those that differ and need a restart to apply
+    pub fn keep_startup_sections(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut changed = vec![];
+        keep("scan", &mut self.scan, &running.scan, &mut changed);
+        keep("capture", &mut self.capture, &running.capture, &mut changed);
+        keep("history", &mut self.history, &running.history, &mut changed);
+        keep("metrics", &mut self.metrics, &running.metrics, &mut changed);
+        keep("control", &mut self.control, &running.control, &mut changed);
+        changed
+    }
+
+    /// Scan options, flags given on the command line win over the file
+    pub fn scan_options(&self, ranges: &[IpRange], rate: Option<f64>) -> ScanOptions {
+        let defaults = ScanOptions::default();
+        ScanOptions {
+            ranges: if ranges.is_empty() {
+                self.scan.ranges.clone()
+            } else {
+                ranges.to_vec()
+            },
+            rate: rate.or(self.scan.rate).unwrap_or(DEFAULT_SCAN_RATE),
+            burst: self.scan.burst.unwrap_or(defaults.burst),
+            interval: self
+                .scan
+                .interval
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.interval),
+            jitter: self.scan.jitter.unwrap_or(defaults.jitter),
+            adaptive: self.scan.adaptive.unwrap_or(defaults.adaptive),
+            min_rate: self.scan.min_rate.unwrap_or(defaults.min_rate),
+        }
+    }
+
+    pub fn alias(&self, mac: &MacAddr) -> Option<&str> {
+        self.aliases.get(mac).map(String::as_str)
+    }
+
+    pub fn protected(&self, ip: &IpAddr) -> Option<&ProtectedHost> {
+        self.protected.iter().find(|host| host.ip == *ip)
+    }
+}
+
+fn default_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(SYSTEM_CONFIG)];
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(dir) = user_dir {
+        paths.push(dir.join("arp-watch").join("config.toml"));
+    }
+    paths
+}
+
+/// Overrides `base` with `other`, tables are merged key by key
+fn merge(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(other)) => merge(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(files: &[&str]) -> Result<Config, ConfigError> {
+        let sources: Vec<(PathBuf, String)> = files
+            .iter()
+            .enumerate()
+            .map(|(i, content)| (PathBuf::from(format!("{}.toml", i)), content.to_string()))
+            .collect();
+        Config::parse(&sources)
+    }
+
+    #[test]
+    fn defaults_without_files() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.capture, CaptureConfig::default());
+        assert_eq!(config.history, HistoryConfig::default());
+        assert!(config.sinks.is_empty());
+    }
+
+    #[test]
+    fn user_overrides_system_key_by_key() {
+        let system = r#"
+            [scan]
+            rate = 5.0
+            burst = 2
+            [history]
+            alerts = 50
+            [aliases]
+            "aa:bb:cc:dd:ee:ff" = "gateway"
+        "#;
+        let user = r#"
+            [scan]
+            rate = 20.0
+            [aliases]
+            "11:22:33:44:55:66" = "printer"
+        "#;
+        let config = parse(&[system, user]).unwrap();
+        assert_eq!(config.scan.rate, Some(20.0));
+        // Untouched keys of a section come from the system file
+        assert_eq!(config.scan.burst, Some(2));
+        assert_eq!(config.history.alerts, 50);
+        assert_eq!(config.history.changes, 1000);
+        assert_eq!(
+            config.alias(&"aa:bb:cc:dd:ee:ff".parse().unwrap()),
+            Some("gateway")
+        );
+        assert_eq!(
+            config.alias(&"11:22:33:44:55:66".parse().unwrap()),
+            Some("printer")
+        );
+    }
+
+    #[test]
+    fn arrays_replaced_not_appended() {
+        let system = r#"
+            [[protected]]
+            ip = "192.168.1.1"
+            [[sinks]]
+            type = "log"
+        "#;
+        let user = r#"
+            [[protected]]
+            ip = "192.168.1.254"
+            mac = "aa:bb:cc:dd:ee:ff"
+        "#;
+        let config = parse(&[system, user]).unwrap();
+        assert_eq!(config.protected.len(), 1);
+        assert_eq!(config.protected[0].ip, "192.168.1.254".parse().unwrap());
+        assert_eq!(config.sinks.len(), 1);
+    }
+
+    #[test]
+    fn unknown_fields_rejected() {
+        for (content, key) in [
+            ("colour = \"dark\"", "colour"),
+            ("[scan]\nspeed = 3", "speed"),
+            ("[ui]\ntheme = \"dark\"\nfont = \"mono\"", "font"),
+            ("[[protected]]\nip = \"10.0.0.1\"\nrestor = true", "restor"),
+        ] {
+            let e = parse(&[content]).unwrap_err();
+            assert!(e.cause.contains(key), "{}: {}", content, e.cause);
+        }
+    }
+
+    #[test]
+    fn error_points_to_offending_file() {
+        let e = parse(&["[scan]\nrate = 1.0", "[scan]\nrate = \"fast\""]).unwrap_err();
+        assert_eq!(e.path, PathBuf::from("1.toml"));
+        // Valid alone, but not once merged: reported against the last file
+        let e = parse(&["[[protected]]\nip = \"10.0.0.1\"", "[defense]\nframes = 0"]).unwrap_err();
+        assert_eq!(e.path, PathBuf::from("1.toml"));
+    }
+
+    #[test]
+    fn invalid_values_rejected() {
+        for (content, cause) in [
+            ("[scan]\nrate = 0.0", "scan.rate"),
+            ("[scan]\nrate = -1.0", "scan.rate"),
+            ("[scan]\nrate = nan", "scan.rate"),
+            ("[scan]\nmin_rate = inf", "scan.min_rate"),
+            ("[scan]\ninterval = 99999999", "scan.interval"),
+            ("[scan]\nburst = 0", "scan.burst"),
+            ("[scan]\njitter = 1.5", "scan.jitter"),
+            (
+                "[[protected]]\nip = \"10.0.0.1\"\nrestore = true",
+                "restore requires the mac",
+            ),
+            ("[defense]\nframes = 0", "defense.frames"),
+            ("[defense]\nmin_interval = 0", "defense.min_interval"),
+            ("[capture]\nqueue = 0", "capture.queue"),
+            ("[capture]\nsample = 0", "capture.sample"),
+            ("[history]\nprobes = 0", "history"),
+            (
+                "[[sinks]]\ntype = \"email\"\nserver = \"smtp.lan\"\nusername = \"arp\"\n\
+                 from = \"arp@lan\"\nto = [\"admin@lan\"]",
+                "username given without password",
+            ),
+            (
+                "[[sinks]]\ntype = \"email\"\nserver = \"smtp.lan\"\npassword = \"secret\"\n\
+                 from = \"arp@lan\"\nto = [\"admin@lan\"]",
+                "password given without username",
+            ),
+        ] {
+            let e = parse(&[content]).unwrap_err();
+            assert!(e.cause.contains(cause), "{}: {}", content, e.cause);
+        }
+    }
+
+    #[test]
+    fn startup_sections_kept_on_reload() {
+        let running =
+            parse(&["[scan]\nrate = 5.0\n[metrics]\nlisten = \"127.0.0.1:9108\""]).unwrap();
+        let mut reloaded = parse(&[
+            "[scan]\nrate = 50.0\n[metrics]\nlisten = \"127.0.0.1:9108\"\n[ui]\ntheme = \"light\"",
+        ])
+        .unwrap();
+        let pending = reloaded.keep_startup_sections(&running);
+        assert_eq!(pending, ["scan"]);
+        assert_eq!(reloaded.scan.rate, Some(5.0));
+        // Applied on reload
+        assert_eq!(reloaded.ui.theme, Theme::Light);
+        let mut same = running.clone();
+        assert!(same.keep_startup_sections(&running).is_empty());
+    }
+}
@@ -0,0 +1,539 @@
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    sinks::Sink,
+    sniff::ScanCommand,
+    structs::{
+        event::Event,
+        net::{Device, IpAddr, MacAddr},
+    },
+    App,
+};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// Events waiting for a subscriber, one not reading them is dropped rather than
+// stalling the app
+const SUBSCRIBER_QUEUE: usize = 100;
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const LISTEN_BACKLOG: i32 = 16;
+
+/**
+Requests accepted on the control socket, one json object per line:
+
+{"cmd": "devices"}
+{"cmd": "lookup", "ip": "192.168.1.1"} or {"cmd": "lookup", "mac": "aa:bb:cc:dd:ee:ff"}
+{"cmd": "binding", "iface": "eth0", "ip": "192.168.1.1"}, the mac the ip is
+bound to, whether it settled and whether it is verified: never changed and
+held long enough to get pinned
+{"cmd": "alerts"}
+{"cmd": "ack", "id": 3}, every alert when id is missing, ids stay the same
+as the oldest alerts get dropped
+{"cmd": "rescan", "iface": "eth0"}, every interface when iface is missing
+{"cmd": "subscribe"}
+
+Each request gets a one line answer with "ok" set, then subscribers get
+events as the json sink writes them.
+*/
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Request {
+    Devices,
+    Lookup {
+        ip: Option<IpAddr>,
+        mac: Option<MacAddr>,
+    },
+    Binding {
+        iface: String,
+        ip: IpAddr,
+    },
+    Alerts,
+    Ack {
+        id: Option<usize>,
+    },
+    Rescan {
+        iface: Option<String>,
+    },
+    Subscribe,
+}
+
+#[derive(Debug)]
+pub struct ControlError {
+    pub cause: String,
+}
+
+impl Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Control socket: {}", self.cause)
+    }
+}
+
+impl Error for ControlError {}
+
+/// Request waiting for the app to answer it
+pub struct ControlRequest {
+    pub request: Request,
+    reply: Sender<Value>,
+}
+
+/// Requests of the clients, the socket file is removed when dropped
+pub struct ControlSocket {
+    requests: Receiver<ControlRequest>,
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let ours = fs::symlink_metadata(&self.path)
+            .map(|metadata| metadata.file_type().is_socket())
+            .unwrap_or(false);
+        if ours {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Can't remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+// Queues of the subscribers, each written to by its own thread
+type Subscribers = Arc<Mutex<Vec<SyncSender<String>>>>;
+
+/// Hands events to the clients that subscribed
+pub struct SubscribersSink {
+    subscribers: Subscribers,
+}
+
+impl Sink for SubscribersSink {
+    fn emit(&mut self, event: &Event) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+        let line = event.to_json();
+        // Full when the subscriber stopped reading, disconnected once its writer gave up
+        subscribers.retain(|tx| tx.try_send(line.clone()).is_ok());
+    }
+}
+
+/// Listens on the socket, requests are handed to the app through the socket handle
+pub fn serve(path: &Path) -> Result<(ControlSocket, SubscribersSink), Box<dyn Error>> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        // Never remove what may be an ordinary file behind a mistyped path
+        if !metadata.file_type().is_socket() {
+            return Err(Box::new(ControlError {
+                cause: format!("{} exists and is not a socket", path.display()),
+            }));
+        }
+        // A socket left by a previous run refuses connections
+        if UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+    }
+    let listener = bind(path).map_err(|e| ControlError {
+        cause: format!("Can't listen on {}: {}", path.display(), e),
+    })?;
+    info!("Control socket listening on {}", path.display());
+
+    let (tx, rx) = mpsc::channel();
+    let subscribers = Subscribers::default();
+    let sink = SubscribersSink {
+        subscribers: subscribers.clone(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    let subscribers = subscribers.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = client(stream, &tx, &subscribers) {
+                            warn!("Control client failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Control socket failed: {}", e),
+            }
+        }
+    });
+    let socket = ControlSocket {
+        requests: rx,
+        path: path.to_owned(),
+    };
+    Ok((socket, sink))
+}
+
+/**
+Binds a stream socket to the path, readable and writable by the owner and group
+only. The mode is set between bind and listen: connections are refused until
+listen, so nobody else can get in meanwhile. Changing the umask instead would
+affect the files created by every other thread of the process.
+*/
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    let name = path.as_os_str().as_bytes();
+    // SAFETY: an all zero sockaddr_un is valid
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    // Nul terminated
+    if name.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path too long"));
+    }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(name) {
+        *dst = *src as libc::c_char;
+    }
+    // SAFETY: plain syscall, the fd is owned as soon as it is valid
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the fd was just created and nothing else owns it
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: the address is a valid sockaddr_un for its length
+    let res = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o660)) {
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    // SAFETY: plain syscall on a bound socket
+    if unsafe { libc::listen(fd.as_raw_fd(), LISTEN_BACKLOG) } < 0 {
+        let e = io::Error::last_os_error();
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(UnixListener::from(fd))
+}
+
+/// Writes the queued events to the subscriber until it stops reading
+fn subscribe(stream: &UnixStream, subscribers: &Subscribers) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    out.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT))?;
+    let (tx, events) = mpsc::sync_channel::<String>(SUBSCRIBER_QUEUE);
+    thread::spawn(move || {
+        for line in events {
+            if writeln!(out, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.push(tx);
+    }
+    Ok(())
+}
+
+fn client(
+    stream: UnixStream,
+    tx: &Sender<ControlRequest>,
+    subscribers: &Subscribers,
+) -> std::io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream.try_clone()?;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe) => {
+                // Answered before any event gets written
+                writeln!(out, "{}", json!({ "ok": true }))?;
+                subscribe(&stream, subscribers)?;
+                continue;
+            }
+            Ok(request) => {
+                let (reply, answer) = mpsc::channel();
+                if tx.send(ControlRequest { request, reply }).is_err() {
+                    return Ok(());
+                }
+                answer
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| failure("no answer from the app"))
+            }
+            Err(e) => failure(&e.to_string()),
+        };
+        writeln!(out, "{}", answer)?;
+    }
+    Ok(())
+}
+
+fn failure(cause: &str) -> Value {
+    json!({ "ok": false, "error": cause })
+}
+
+impl App {
+    /// Answers the pending control requests, called by the event loops
+    pub fn poll_control(&mut self) {
+        let requests: Vec<ControlRequest> = match self.control {
+            Some(ref control) => control.requests.try_iter().collect(),
+            None => return,
+        };
+        for ControlRequest { request, reply } in requests {
+            let _ = reply.send(self.answer(&request));
+        }
+    }
+
+    fn answer(&mut self, request: &Request) -> Value {
+        match request {
+            Request::Devices => {
+                let devices: Vec<&Device> = self.devices.iter().collect();
+                json!({ "ok": true, "devices": devices })
+            }
+            Request::Lookup { ip, mac } => {
+                if ip.is_none() && mac.is_none() {
+                    return failure("lookup needs an ip or a mac");
+                }
+                let devices: Vec<&Device> = match (ip, mac) {
+                    (Some(ip), mac) => self
+                        .devices
+                        .find_by_ip(ip)
+                        .filter(|dev| mac.as_ref().map(|mac| dev.mac == *mac).unwrap_or(true))
+                        .collect(),
+                    (None, Some(mac)) => self.devices.find_by_mac(mac).collect(),
+                    (None, None) => vec![],
+                };
+                json!({ "ok": true, "devices": devices })
+            }
+            Request::Binding { iface, ip } => match self.bindings.get(&(iface.clone(), *ip)) {
+                Some(binding) => json!({
+                    "ok": true,
+                    "mac": binding.mac,
+                    "settled": binding.settled(),
+                    "verified": binding.verified(Instant::now()),
+                }),
+                None => failure(&format!("{} not bound on {}", ip, iface)),
+            },
+            Request::Alerts => {
+                let alerts: Vec<Value> = self
+                    .alerts
+                    .numbered()
+                    .map(|(id, alert)| json!({ "id": id, "acked": alert.acked, "alert": alert }))
+                    .collect();
+                json!({ "ok": true, "alerts": alerts })
+            }
+            Request::Ack { id } => match id {
+                Some(id) => match self.alerts.get_mut(*id) {
+                    Some(alert) => {
+                        alert.acked = true;
+                        json!({ "ok": true })
+                    }
+                    None => failure(&format!("no alert {}", id)),
+                },
+                None => {
+                    for alert in self.alerts.items.iter_mut() {
+                        alert.acked = true;
+                    }
+                    json!({ "ok": true })
+                }
+            },
+            Request::Rescan { iface } => match self.rescan(iface.as_deref()) {
+                0 => failure("no scanner running on that interface"),
+                count => json!({ "ok": true, "scanners": count }),
+            },
+            // Handled by the client thread
+            Request::Subscribe => json!({ "ok": true }),
+        }
+    }
+
+    /// Restarts the sweep of the interface, or of every one. Returns the scanners reached.
+    pub fn rescan(&self, iface: Option<&str>) -> usize {
+        self.scan_command(iface, ScanCommand::Rescan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        structs::{
+            alert::{Alert, AlertKind},
+            binding::Binding,
+            event::EventKind,
+        },
+        testing::{self, device, event, scratch_dir},
+    };
+
+    const MAC: &str = "02:00:00:00:00:01";
+
+    fn app(alerts: usize) -> App {
+        let mut config = Config::default();
+        config.history.alerts = alerts;
+        let mut app = testing::app(config);
+        app.devices.upsert(device(MAC, "10.0.0.1", "eth0"));
+        app.devices
+            .upsert(device("02:00:00:00:00:02", "10.0.0.2", "eth0"));
+        app
+    }
+
+    fn request(json: &str) -> Request {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn alert(app: &mut App, ip: &str) {
+        app.alert(Alert::new(
+            AlertKind::UnsolicitedReply,
+            device(MAC, ip, "eth0"),
+            None,
+        ));
+    }
+
+    #[test]
+    fn devices_and_lookups() {
+        let mut app = app(10);
+        let answer = app.answer(&request(r#"{"cmd": "devices"}"#));
+        assert_eq!(answer["ok"], true);
+        assert_eq!(answer["devices"].as_array().unwrap().len(), 2);
+
+        let answer = app.answer(&request(r#"{"cmd": "lookup", "ip": "10.0.0.2"}"#));
+        assert_eq!(answer["devices"][0]["mac"], "02:00:00:00:00:02");
+        let answer = app.answer(&request(
+            r#"{"cmd": "lookup", "ip": "10.0.0.2", "mac": "02:00:00:00:00:01"}"#,
+        ));
+        assert_eq!(answer["devices"].as_array().unwrap().len(), 0);
+        let answer = app.answer(&request(r#"{"cmd": "lookup", "mac": "02:00:00:00:00:01"}"#));
+        assert_eq!(answer["devices"][0]["ip"], "10.0.0.1");
+        let answer = app.answer(&request(r#"{"cmd": "lookup"}"#));
+        assert_eq!(answer["ok"], false);
+    }
+
+    #[test]
+    fn bindings() {
+        let mut app = app(10);
+        let ip = "10.0.0.1".parse().unwrap();
+        app.bindings.insert(
+            ("eth0".to_owned(), ip),
+            Binding::new(MAC.parse().unwrap(), Instant::now()),
+        );
+        let answer = app.answer(&request(
+            r#"{"cmd": "binding", "iface": "eth0", "ip": "10.0.0.1"}"#,
+        ));
+        assert_eq!(answer["ok"], true);
+        assert_eq!(answer["mac"], "02:00:00:00:00:01");
+        assert_eq!(answer["verified"], false);
+        let answer = app.answer(&request(
+            r#"{"cmd": "binding", "iface": "eth1", "ip": "10.0.0.1"}"#,
+        ));
+        assert_eq!(answer["ok"], false);
+    }
+
+    #[test]
+    fn ack_ids_past_evicted_alerts() {
+        let mut app = app(2);
+        for ip in ["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+            alert(&mut app, ip);
+        }
+        let answer = app.answer(&request(r#"{"cmd": "alerts"}"#));
+        let ids: Vec<u64> = answer["alerts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|alert| alert["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, [1, 2]);
+
+        // The first alert was dropped, its id isn't reused
+        let answer = app.answer(&request(r#"{"cmd": "ack", "id": 0}"#));
+        assert_eq!(answer["ok"], false);
+        let answer = app.answer(&request(r#"{"cmd": "ack", "id": 2}"#));
+        assert_eq!(answer["ok"], true);
+        let acked: Vec<bool> = app.alerts.items.iter().map(|alert| alert.acked).collect();
+        assert_eq!(acked, [false, true]);
+        assert_eq!(app.alerts.items[1].device.ip, "10.0.0.3".parse().unwrap());
+
+        alert(&mut app, "10.0.0.4");
+        app.answer(&request(r#"{"cmd": "ack"}"#));
+        assert!(app.alerts.items.iter().all(|alert| alert.acked));
+    }
+
+    #[test]
+    fn rescan_without_scanner() {
+        let mut app = app(10);
+        let answer = app.answer(&request(r#"{"cmd": "rescan", "iface": "eth0"}"#));
+        assert_eq!(answer["ok"], false);
+    }
+
+    #[test]
+    fn unknown_requests_rejected() {
+        assert!(serde_json::from_str::<Request>(r#"{"cmd": "reboot"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"cmd": "ack", "all": true}"#).is_err());
+    }
+
+    #[test]
+    fn non_socket_path_refused() {
+        let dir = scratch_dir("control-file");
+        let path = dir.join("control.sock");
+        fs::write(&path, "keep me").unwrap();
+        assert!(bind(&path).is_err());
+        assert!(serve(&path).is_err());
+        // Never removed
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_socket_replaced() {
+        let dir = scratch_dir("control-stale");
+        let path = dir.join("control.sock");
+        // Left behind by a previous run, nobody listening anymore
+        drop(bind(&path).unwrap());
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_socket());
+        let (socket, _) = serve(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(socket);
+        assert!(fs::symlink_metadata(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn slow_subscribers_dropped() {
+        let subscribers = Subscribers::default();
+        let (slow, _slow_rx) = mpsc::sync_channel(1);
+        let (gone, gone_rx) = mpsc::sync_channel(1);
+        let (reading, reading_rx) = mpsc::sync_channel(1);
+        drop(gone_rx);
+        subscribers.lock().unwrap().extend([slow, gone, reading]);
+        let mut sink = SubscribersSink {
+            subscribers: subscribers.clone(),
+        };
+        let event = event(EventKind::NewDevice(device(MAC, "10.0.0.1", "eth0")));
+        sink.emit(&event);
+        assert_eq!(subscribers.lock().unwrap().len(), 2);
+        assert_eq!(reading_rx.try_recv().unwrap(), event.to_json());
+        // The slow one never read its first event, its queue is full
+        sink.emit(&event);
+        assert_eq!(subscribers.lock().unwrap().len(), 1);
+        assert!(reading_rx.try_recv().is_ok());
+    }
+}
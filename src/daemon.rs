@@ -0,0 +1,69 @@
+use log::{error, info, warn};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::App;
+
+// Dropped packets are logged at most once per interval while drops go on
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Runs the detection pipeline without UI until SIGTERM or SIGINT,
+/// events only go to the sinks. SIGHUP reloads the config.
+pub fn run_daemon(mut app: App) -> Result<(), Box<dyn Error>> {
+    let term = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, term.clone())?;
+    signal_hook::flag::register(SIGINT, term.clone())?;
+    let sighup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, sighup.clone())?;
+
+    info!("Watching {}", app.ifaces.join(", "));
+    let mut dropped_seen = 0;
+    let mut drops_checked = Instant::now();
+    let res = loop {
+        if term.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+        if sighup.swap(false, Ordering::Relaxed) {
+            match app.reload_config() {
+                Ok(pending) if pending.is_empty() => info!("Config reloaded"),
+                Ok(pending) => warn!(
+                    "Config reloaded, restart to apply the changes of [{}]",
+                    pending.join(", ")
+                ),
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        app.poll_control();
+        app.poll_scans();
+        app.poll_kernel();
+        match app.rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(capture) => app.handle_packet(capture),
+            Err(RecvTimeoutError::Timeout) => app.tick(Instant::now()),
+            Err(RecvTimeoutError::Disconnected) => break Err("No interface left to watch".into()),
+        }
+        if drops_checked.elapsed() >= DROP_REPORT_INTERVAL {
+            let dropped = app.rx.dropped();
+            if dropped > dropped_seen {
+                warn!(
+                    "{} packets dropped, processing can't keep up with the capture",
+                    dropped - dropped_seen
+                );
+            }
+            dropped_seen = dropped;
+            drops_checked = Instant::now();
+        }
+    };
+
+    info!("Shutting down");
+    app.sinks.flush();
+    res
+}
@@ -0,0 +1,117 @@
+use log::{error, warn};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::DefenseConfig,
+    metrics::CORRECTIVE_SENT,
+    sniff::{find_interface, local_mac, open_channel},
+    structs::{
+        arp::ArpPacket,
+        net::{Device, IpAddr, MacAddr},
+    },
+};
+
+// Between two frames of the same correction
+const FRAME_SPACING: Duration = Duration::from_secs(1);
+
+/**
+Restores the binding of hijacked protected hosts, for those opted in with
+`restore`: gratuitous replies announcing the legitimate mac are broadcast
+from our own interface. Corrections of a host are at least `min_interval`
+apart, however often the hijacker answers.
+*/
+#[derive(Debug, Default)]
+pub struct Defense {
+    // Last correction of each host, by interface and ip
+    last: HashMap<(String, IpAddr), Instant>,
+}
+
+impl Defense {
+    /// Sends the correction in the background, returns false when rate limited
+    pub fn restore(&mut self, legitimate: &Device, config: &DefenseConfig, now: Instant) -> bool {
+        if !self.allowed(legitimate, Duration::from_secs(config.min_interval), now) {
+            return false;
+        }
+        let legitimate = legitimate.clone();
+        let frames = config.frames;
+        thread::spawn(move || {
+            if let Err(e) = correct(&legitimate, frames) {
+                error!("Can't restore {}: {}", legitimate, e);
+            }
+        });
+        true
+    }
+
+    /// Whether the host may be corrected at `now`, recording the correction if so
+    fn allowed(&mut self, legitimate: &Device, min_interval: Duration, now: Instant) -> bool {
+        let key = (legitimate.iface.clone(), legitimate.ip);
+        if let Some(last) = self.last.get(&key) {
+            if now.saturating_duration_since(*last) < min_interval {
+                return false;
+            }
+        }
+        self.last.insert(key, now);
+        true
+    }
+}
+
+fn correct(legitimate: &Device, frames: u32) -> Result<(), Box<dyn Error>> {
+    let interface = find_interface(&legitimate.iface)?;
+    let local_mac = local_mac(&interface)?;
+    let (mut tx, _) = open_channel(&interface, Default::default())?;
+    let packet = ArpPacket::gratuitous(legitimate.mac, legitimate.ip);
+    // Sent from our own mac, so that switches don't move the host port to us
+    let raw = packet.raw_padded(&local_mac, &MacAddr::BROADCAST)?;
+    for frame in 1..=frames {
+        if let Some(Err(e)) = tx.send_to(raw.as_slice(), None) {
+            return Err(Box::new(e));
+        }
+        CORRECTIVE_SENT.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "Corrective ARP {}/{} on {}: {} is at {}",
+            frame, frames, legitimate.iface, legitimate.ip, legitimate.mac
+        );
+        if frame < frames {
+            thread::sleep(FRAME_SPACING);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::device;
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    fn host(ip: &str) -> Device {
+        device("02:00:00:00:00:01", ip, "eth0")
+    }
+
+    #[test]
+    fn rate_limited_per_host() {
+        let mut defense = Defense::default();
+        let start = Instant::now();
+        let gateway = host("192.168.1.1");
+        // First correction of the host
+        assert!(defense.allowed(&gateway, INTERVAL, start));
+        // A spoofing storm within the interval gets no more
+        for secs in [0, 1, 29] {
+            let at = start + Duration::from_secs(secs);
+            assert!(!defense.allowed(&gateway, INTERVAL, at));
+        }
+        // Other hosts aren't held back
+        assert!(defense.allowed(&host("192.168.1.2"), INTERVAL, start));
+        // Once over, corrected again and the interval starts over
+        let later = start + INTERVAL;
+        assert!(defense.allowed(&gateway, INTERVAL, later));
+        assert!(!defense.allowed(&gateway, INTERVAL, later + Duration::from_secs(1)));
+    }
+}
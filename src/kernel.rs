@@ -0,0 +1,649 @@
+use log::{error, warn};
+use std::{
+    error::Error,
+    ffi::{CStr, CString},
+    fmt::Display,
+    fs, io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crate::{
+    structs::{
+        alert::{Alert, AlertKind},
+        net::{Device, IpAddr, MacAddr},
+    },
+    App,
+};
+
+pub const PROC_ARP: &str = "/proc/net/arp";
+// Multicast group of neighbour table changes, missing from libc
+const RTMGRP_NEIGH: u32 = 0x4;
+// Flags of /proc/net/arp entries
+const ATF_COM: u32 = 0x2;
+const ATF_PERM: u32 = 0x4;
+const NLMSG_HEADER_LEN: usize = 16;
+const NDMSG_LEN: usize = 12;
+
+/// State of a kernel neighbour entry, as shown by `ip neigh`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum NeighState {
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+    Failed,
+    NoArp,
+    Permanent,
+    // Resolved, /proc/net/arp doesn't tell more
+    Complete,
+    None,
+}
+
+impl NeighState {
+    fn from_nud(state: u16) -> NeighState {
+        match state {
+            libc::NUD_INCOMPLETE => NeighState::Incomplete,
+            libc::NUD_REACHABLE => NeighState::Reachable,
+            libc::NUD_STALE => NeighState::Stale,
+            libc::NUD_DELAY => NeighState::Delay,
+            libc::NUD_PROBE => NeighState::Probe,
+            libc::NUD_FAILED => NeighState::Failed,
+            libc::NUD_NOARP => NeighState::NoArp,
+            libc::NUD_PERMANENT => NeighState::Permanent,
+            _ => NeighState::None,
+        }
+    }
+
+    /// Whether the kernel sends to the mac of the entry
+    pub fn resolved(&self) -> bool {
+        !matches!(
+            self,
+            NeighState::Incomplete | NeighState::Failed | NeighState::None
+        )
+    }
+}
+
+impl Display for NeighState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NeighState::Incomplete => "incomplete",
+            NeighState::Reachable => "reachable",
+            NeighState::Stale => "stale",
+            NeighState::Delay => "delay",
+            NeighState::Probe => "probe",
+            NeighState::Failed => "failed",
+            NeighState::NoArp => "noarp",
+            NeighState::Permanent => "permanent",
+            NeighState::Complete => "complete",
+            NeighState::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Entry of the kernel neighbour table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbour {
+    pub iface: String,
+    pub ip: IpAddr,
+    pub mac: Option<MacAddr>,
+    pub state: NeighState,
+}
+
+impl Display for Neighbour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mac {
+            Some(ref mac) => write!(f, "{} ({})", mac, self.state),
+            None => write!(f, "- ({})", self.state),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NeighbourUpdate {
+    Set(Neighbour),
+    Removed { iface: String, ip: IpAddr },
+    // Whole table, replacing every entry known so far
+    Snapshot(Vec<Neighbour>),
+}
+
+/// Entries of /proc/net/arp, or of a file in the same format
+pub fn read_proc(path: &Path) -> io::Result<Vec<Neighbour>> {
+    Ok(parse_proc(&fs::read_to_string(path)?))
+}
+
+fn parse_proc(content: &str) -> Vec<Neighbour> {
+    // IP address, HW type, Flags, HW address, Mask, Device
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+            let state = if flags & ATF_PERM != 0 {
+                NeighState::Permanent
+            } else if flags & ATF_COM != 0 {
+                NeighState::Complete
+            } else {
+                NeighState::Incomplete
+            };
+            Some(Neighbour {
+                iface: fields[5].to_owned(),
+                ip: fields[0].parse().ok()?,
+                mac: fields[3].parse().ok().filter(|mac| *mac != MacAddr::ZERO),
+                state,
+            })
+        })
+        .collect()
+}
+
+/**
+Follows the ipv4 neighbour table of the kernel: the entries of /proc/net/arp
+come first, then the changes notified over rtnetlink.
+*/
+pub fn watch() -> Result<Receiver<NeighbourUpdate>, Box<dyn Error>> {
+    // Subscribed before reading the table, so that no change is missed
+    let socket = Netlink::open(RTMGRP_NEIGH)?;
+    let (tx, rx) = mpsc::channel();
+    send_table(&tx)?;
+    thread::spawn(move || {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // Changes came faster than read, the table is read again and
+                    // replaces the known one, deletions included
+                    warn!("Kernel neighbour changes lost, reading the table again");
+                    if send_table(&tx).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) => return error!("Can't follow the kernel neighbour table: {}", e),
+            };
+            for update in parse_messages(&buf[..len]) {
+                if tx.send(update).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn send_table(tx: &Sender<NeighbourUpdate>) -> Result<(), Box<dyn Error>> {
+    let table = read_proc(Path::new(PROC_ARP))?;
+    tx.send(NeighbourUpdate::Snapshot(table))?;
+    Ok(())
+}
+
+/// Route netlink socket
+pub struct Netlink {
+    fd: OwnedFd,
+}
+
+impl Netlink {
+    /// Opens a socket listening to the multicast `groups`
+    pub fn open(groups: u32) -> io::Result<Netlink> {
+        // SAFETY: plain syscalls, the fd is owned as soon as it is valid
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = groups;
+            let res = libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Netlink { fd })
+        }
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: the buffer is valid for its length
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+
+    /// Sends a request to the kernel and waits for its acknowledgement
+    pub fn request(&self, kind: u16, flags: u16, payload: &[u8]) -> io::Result<()> {
+        let len = NLMSG_HEADER_LEN + payload.len();
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(
+            &(flags | (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16).to_ne_bytes(),
+        );
+        // Sequence number and port id, a single request is in flight
+        msg.extend_from_slice(&1u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        // SAFETY: the message is valid for its length
+        let sent = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0; 8192];
+        loop {
+            let len = self.recv(&mut buf)?;
+            if let Some(errno) = ack(&buf[..len]) {
+                return match errno {
+                    0 => Ok(()),
+                    errno => Err(io::Error::from_raw_os_error(-errno)),
+                };
+            }
+        }
+    }
+}
+
+/// Error code of the acknowledgement in a batch of netlink messages, 0 on success
+fn ack(mut buf: &[u8]) -> Option<i32> {
+    while buf.len() >= NLMSG_HEADER_LEN + 4 {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        if len < NLMSG_HEADER_LEN || len > buf.len() {
+            return None;
+        }
+        if i32::from(kind) == libc::NLMSG_ERROR {
+            let error = &buf[NLMSG_HEADER_LEN..NLMSG_HEADER_LEN + 4];
+            return Some(i32::from_ne_bytes([error[0], error[1], error[2], error[3]]));
+        }
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    None
+}
+
+/// Installs a permanent entry binding the ip to the mac, replacing the current one
+pub fn pin(iface: &str, ip: &IpAddr, mac: &MacAddr) -> io::Result<()> {
+    let mut payload = ndmsg(iface, libc::NUD_PERMANENT)?;
+    attribute(&mut payload, libc::NDA_DST, &ip.octets());
+    attribute(&mut payload, libc::NDA_LLADDR, &mac.octets());
+    let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    Netlink::open(0)?.request(libc::RTM_NEWNEIGH, flags, &payload)
+}
+
+/// Removes the entry of the ip, whatever its state
+pub fn unpin(iface: &str, ip: &IpAddr) -> io::Result<()> {
+    let mut payload = ndmsg(iface, 0)?;
+    attribute(&mut payload, libc::NDA_DST, &ip.octets());
+    Netlink::open(0)?.request(libc::RTM_DELNEIGH, 0, &payload)
+}
+
+fn ndmsg(iface: &str, state: u16) -> io::Result<Vec<u8>> {
+    let name = CString::new(iface).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: the name is nul terminated
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut payload = vec![libc::AF_INET as u8, 0, 0, 0];
+    payload.extend_from_slice(&(ifindex as i32).to_ne_bytes());
+    payload.extend_from_slice(&state.to_ne_bytes());
+    // Flags and type
+    payload.extend_from_slice(&[0, 0]);
+    Ok(payload)
+}
+
+fn attribute(payload: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    let len = 4 + data.len();
+    payload.extend_from_slice(&(len as u16).to_ne_bytes());
+    payload.extend_from_slice(&kind.to_ne_bytes());
+    payload.extend_from_slice(data);
+    payload.resize(payload.len() + align(len) - len, 0);
+}
+
+/// Neighbour changes of a batch of netlink messages, others are skipped
+fn parse_messages(mut buf: &[u8]) -> Vec<NeighbourUpdate> {
+    let mut updates = vec![];
+    while buf.len() >= NLMSG_HEADER_LEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        if len < NLMSG_HEADER_LEN || len > buf.len() {
+            break;
+        }
+        let payload = &buf[NLMSG_HEADER_LEN..len];
+        let update = match kind {
+            libc::RTM_NEWNEIGH => parse_neighbour(payload).map(NeighbourUpdate::Set),
+            libc::RTM_DELNEIGH => {
+                parse_neighbour(payload).map(|neighbour| NeighbourUpdate::Removed {
+                    iface: neighbour.iface,
+                    ip: neighbour.ip,
+                })
+            }
+            _ => None,
+        };
+        updates.extend(update);
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    updates
+}
+
+/// Ipv4 neighbour of a ndmsg and its attributes
+fn parse_neighbour(payload: &[u8]) -> Option<Neighbour> {
+    if payload.len() < NDMSG_LEN || i32::from(payload[0]) != libc::AF_INET {
+        return None;
+    }
+    let ifindex = i32::from_ne_bytes([payload[4], payload[5], payload[6], payload[7]]);
+    let state = u16::from_ne_bytes([payload[8], payload[9]]);
+    let mut ip = None;
+    let mut mac = None;
+    let mut attrs = &payload[NDMSG_LEN..];
+    while attrs.len() >= 4 {
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+        if len < 4 || len > attrs.len() {
+            break;
+        }
+        let data = &attrs[4..len];
+        match kind {
+            libc::NDA_DST => ip = IpAddr::new(data).ok(),
+            libc::NDA_LLADDR => mac = MacAddr::new(data).ok(),
+            _ => (),
+        }
+        attrs = &attrs[align(len).min(attrs.len())..];
+    }
+    Some(Neighbour {
+        iface: iface_name(ifindex)?,
+        ip: ip?,
+        mac,
+        state: NeighState::from_nud(state),
+    })
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub fn iface_name(ifindex: i32) -> Option<String> {
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: the buffer holds IF_NAMESIZE bytes as required
+    let res = unsafe { libc::if_indextoname(ifindex as libc::c_uint, name.as_mut_ptr()) };
+    if res.is_null() {
+        return None;
+    }
+    // SAFETY: if_indextoname wrote a nul terminated name
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+impl App {
+    /// Applies the changes of the kernel neighbour table, returns true when there were some
+    pub fn poll_kernel(&mut self) -> bool {
+        let Some(ref kernel_rx) = self.kernel_rx else {
+            return false;
+        };
+        let updates: Vec<NeighbourUpdate> = kernel_rx.try_iter().collect();
+        let updated = !updates.is_empty();
+        for update in updates {
+            match update {
+                NeighbourUpdate::Set(neighbour) => {
+                    let key = (neighbour.iface.clone(), neighbour.ip);
+                    self.kernel.insert(key.clone(), neighbour);
+                    self.check_kernel(&key.0, &key.1);
+                }
+                NeighbourUpdate::Removed { iface, ip } => {
+                    self.kernel.remove(&(iface, ip));
+                }
+                NeighbourUpdate::Snapshot(table) => {
+                    self.kernel.clear();
+                    for neighbour in table {
+                        let key = (neighbour.iface.clone(), neighbour.ip);
+                        self.kernel.insert(key, neighbour);
+                    }
+                    self.kernel_reported
+                        .retain(|key, _| self.kernel.contains_key(key));
+                    let keys: Vec<(String, IpAddr)> = self.kernel.keys().cloned().collect();
+                    for (iface, ip) in keys {
+                        self.check_kernel(&iface, &ip);
+                    }
+                }
+            }
+        }
+        updated
+    }
+
+    /// Alerts when the kernel sends to another mac than the trusted one of the ip,
+    /// once per kernel binding. The configured mac of protected hosts is trusted,
+    /// otherwise the binding seen on the wire once it settled.
+    pub(crate) fn check_kernel(&mut self, iface: &str, ip: &IpAddr) {
+        let key = (iface.to_owned(), *ip);
+        let Some(cached) = self.kernel.get(&key).and_then(|neighbour| {
+            neighbour
+                .state
+                .resolved()
+                .then_some(neighbour.mac)
+                .flatten()
+        }) else {
+            self.kernel_reported.remove(&key);
+            return;
+        };
+        let configured = self.config.protected(ip).and_then(|host| host.mac);
+        let trusted = match (configured, self.bindings.get(&key)) {
+            (Some(mac), _) => mac,
+            (None, Some(binding)) if binding.settled() => binding.mac,
+            // Unknown yet, or rebinding: the last mac seen may well be the poisoner
+            _ => return,
+        };
+        if cached == trusted {
+            self.kernel_reported.remove(&key);
+            return;
+        }
+        if self.kernel_reported.get(&key) == Some(&cached) {
+            return;
+        }
+        self.kernel_reported.insert(key, cached);
+        let device = Device {
+            mac: cached,
+            ip: *ip,
+            iface: iface.to_owned(),
+        };
+        let expected = Device {
+            mac: trusted,
+            ..device.clone()
+        };
+        self.alert(Alert::new(
+            AlertKind::PoisonedKernelCache,
+            device,
+            Some(expected),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0xef]);
+    // Loopback, always the first interface of a network namespace
+    const LO: i32 = 1;
+
+    #[test]
+    fn proc_entries() {
+        let content = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         00:1a:2b:3c:4d:ef     *        eth0
+192.168.1.2      0x1         0x6         00:1a:2b:3c:4d:ef     *        eth0
+192.168.1.3      0x1         0x0         00:00:00:00:00:00     *        wlan0
+192.168.1.4      0x1         0x2         not-a-mac             *        eth0
+not-an-ip        0x1         0x2         00:1a:2b:3c:4d:ef     *        eth0
+192.168.1.6      0x1         0x2
+";
+        let neighbours = parse_proc(content);
+        let entry = |ip: &str, iface: &str, mac, state| Neighbour {
+            iface: iface.to_owned(),
+            ip: ip.parse().unwrap(),
+            mac,
+            state,
+        };
+        assert_eq!(
+            neighbours,
+            vec![
+                entry("192.168.1.1", "eth0", Some(MAC), NeighState::Complete),
+                entry("192.168.1.2", "eth0", Some(MAC), NeighState::Permanent),
+                entry("192.168.1.3", "wlan0", None, NeighState::Incomplete),
+                entry("192.168.1.4", "eth0", None, NeighState::Complete),
+            ]
+        );
+    }
+
+    fn nlmsg(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = NLMSG_HEADER_LEN + payload.len();
+        let mut msg = (len as u32).to_ne_bytes().to_vec();
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&[0; 10]);
+        msg.extend_from_slice(payload);
+        msg.resize(align(len), 0);
+        msg
+    }
+
+    fn neighbour(family: u8, state: u16, ip: Option<[u8; 4]>, mac: Option<MacAddr>) -> Vec<u8> {
+        let mut payload = vec![family, 0, 0, 0];
+        payload.extend_from_slice(&LO.to_ne_bytes());
+        payload.extend_from_slice(&state.to_ne_bytes());
+        payload.extend_from_slice(&[0, 0]);
+        // Attributes unknown to the parser are skipped
+        attribute(&mut payload, libc::NDA_CACHEINFO, &[0; 16]);
+        if let Some(ip) = ip {
+            attribute(&mut payload, libc::NDA_DST, &ip);
+        }
+        if let Some(mac) = mac {
+            attribute(&mut payload, libc::NDA_LLADDR, &mac.octets());
+        }
+        payload
+    }
+
+    #[test]
+    fn netlink_neighbours() {
+        let ip = [10, 0, 0, 1];
+        let mut buf = vec![];
+        buf.extend(nlmsg(
+            libc::RTM_NEWNEIGH,
+            &neighbour(
+                libc::AF_INET as u8,
+                libc::NUD_REACHABLE,
+                Some(ip),
+                Some(MAC),
+            ),
+        ));
+        // Not a neighbour message
+        buf.extend(nlmsg(libc::RTM_NEWLINK, &[0; 16]));
+        // Ipv6 neighbour
+        buf.extend(nlmsg(
+            libc::RTM_NEWNEIGH,
+            &neighbour(
+                libc::AF_INET6 as u8,
+                libc::NUD_REACHABLE,
+                Some(ip),
+                Some(MAC),
+            ),
+        ));
+        // No destination
+        buf.extend(nlmsg(
+            libc::RTM_NEWNEIGH,
+            &neighbour(libc::AF_INET as u8, libc::NUD_STALE, None, Some(MAC)),
+        ));
+        buf.extend(nlmsg(
+            libc::RTM_NEWNEIGH,
+            &neighbour(libc::AF_INET as u8, libc::NUD_INCOMPLETE, Some(ip), None),
+        ));
+        buf.extend(nlmsg(
+            libc::RTM_DELNEIGH,
+            &neighbour(libc::AF_INET as u8, libc::NUD_FAILED, Some(ip), None),
+        ));
+        // Truncated message ends the batch
+        let mut truncated = nlmsg(
+            libc::RTM_NEWNEIGH,
+            &neighbour(
+                libc::AF_INET as u8,
+                libc::NUD_REACHABLE,
+                Some(ip),
+                Some(MAC),
+            ),
+        );
+        truncated.truncate(truncated.len() - 8);
+        buf.extend(truncated);
+
+        let iface = iface_name(LO).unwrap();
+        let updates = parse_messages(&buf);
+        assert_eq!(updates.len(), 3, "{:?}", updates);
+        match &updates[0] {
+            NeighbourUpdate::Set(neighbour) => assert_eq!(
+                *neighbour,
+                Neighbour {
+                    iface: iface.clone(),
+                    ip: IpAddr::new(&ip).unwrap(),
+                    mac: Some(MAC),
+                    state: NeighState::Reachable,
+                }
+            ),
+            update => panic!("{:?}", update),
+        }
+        match &updates[1] {
+            NeighbourUpdate::Set(neighbour) => {
+                assert_eq!(neighbour.mac, None);
+                assert_eq!(neighbour.state, NeighState::Incomplete);
+                assert!(!neighbour.state.resolved());
+            }
+            update => panic!("{:?}", update),
+        }
+        match &updates[2] {
+            NeighbourUpdate::Removed {
+                iface: removed,
+                ip: removed_ip,
+            } => {
+                assert_eq!(*removed, iface);
+                assert_eq!(*removed_ip, IpAddr::new(&ip).unwrap());
+            }
+            update => panic!("{:?}", update),
+        }
+    }
+
+    #[test]
+    fn netlink_ack() {
+        let mut error = (-libc::EPERM).to_ne_bytes().to_vec();
+        error.extend_from_slice(&[0; 16]);
+        let mut buf = nlmsg(libc::RTM_NEWNEIGH, &[0; 12]);
+        assert_eq!(ack(&buf), None);
+        buf.extend(nlmsg(libc::NLMSG_ERROR as u16, &error));
+        assert_eq!(ack(&buf), Some(-libc::EPERM));
+        assert_eq!(ack(&nlmsg(libc::NLMSG_ERROR as u16, &[0; 20])), Some(0));
+    }
+}
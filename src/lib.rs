@@ -1,13 +1,47 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
+};
 
+pub mod config;
+pub mod control;
+pub mod daemon;
+pub mod defense;
+pub mod kernel;
+pub mod metrics;
+pub mod monitor;
+pub mod oui;
+pub mod pacing;
+pub mod pcap;
+pub mod probe;
+pub mod report;
+pub mod sinks;
 pub mod sniff;
 pub mod structs;
+#[cfg(test)]
+mod testing;
 pub mod ui;
 
-use structs::net::Device;
+use structs::net::{Device, DeviceKey, IpAddr, MacAddr};
 
-use crate::structs::arp::ArpPacket;
+use crate::config::Config;
+use crate::control::ControlSocket;
+use crate::defense::Defense;
+use crate::kernel::{Neighbour, NeighbourUpdate};
+use crate::metrics::{Counters, Snapshot};
+use crate::probe::ProbeReport;
+use crate::sinks::Sinks;
+use crate::sniff::{ScanCommand, ScanProgress};
+use crate::structs::alert::Alert;
+use crate::structs::arp::CapturedPacket;
+use crate::structs::binding::Binding;
+use crate::structs::pending::PendingRequests;
+use crate::structs::queue::{self, BoundedReceiver, BoundedSender};
 use crate::structs::statelist::StateList;
+use crate::structs::stats::DeviceStats;
+use crate::structs::store::DeviceStore;
 
 pub enum InputMode {
     EditMode,
@@ -16,49 +50,232 @@ pub enum InputMode {
 
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub enum Popup {
-    GotoCommand,
+    Probe,
+    Rescan,
     None,
 }
 
 pub struct App {
-    // TODO: Change string for a made struct
-    pub list: StateList<Device>,
+    pub devices: DeviceStore,
+    // Selected row of the device table, among the visible devices
+    pub selected: Option<usize>,
     pub changement_list: StateList<(Device, Device)>,
-    pub rx: Receiver<ArpPacket>,
+    pub probe_list: StateList<ProbeReport>,
+    pub alerts: StateList<Alert>,
+    pub stats: HashMap<DeviceKey, DeviceStats>,
+    // Mac each ip is bound to, by interface and ip
+    pub bindings: HashMap<(String, IpAddr), Binding>,
+    // Outstanding requests of each interface
+    pub pending: HashMap<String, PendingRequests>,
+    pub rx: BoundedReceiver<CapturedPacket>,
+    // Outcome of the probes started from the UI, the error when one failed
+    pub probe_tx: Sender<Result<ProbeReport, String>>,
+    pub probe_rx: Receiver<Result<ProbeReport, String>>,
+    pub ifaces: Vec<String>,
+    // Command channel of the scanner of each interface
+    pub scanners: HashMap<String, Sender<ScanCommand>>,
+    pub progress_tx: Sender<ScanProgress>,
+    pub progress_rx: Receiver<ScanProgress>,
+    // Last progress reported by the scanner of each interface
+    pub scans: HashMap<String, ScanProgress>,
+    // Kernel neighbour table by interface and ip, when followed
+    pub kernel: HashMap<(String, IpAddr), Neighbour>,
+    pub kernel_rx: Option<Receiver<NeighbourUpdate>>,
+    // Kernel mac last alerted on for each binding
+    kernel_reported: HashMap<(String, IpAddr), MacAddr>,
+    // Corrective ARP for protected hosts, disabled when not watching live
+    pub defense: Option<Defense>,
+    // Requests from the control socket, when enabled
+    pub control: Option<ControlSocket>,
+    // Index in ifaces of the interface displayed, all of them if None
+    pub view: Option<usize>,
+    // First visible device row
     scroll: usize,
     pub mode: InputMode,
     pub popup: Popup,
     pub input: String,
+    // Outcome of the last user action, shown in the header
+    pub status: String,
     pub arp_frame_counter: usize,
+    pub counters: Counters,
+    // Published on every tick when the metrics endpoint is enabled
+    pub metrics: Option<Snapshot>,
+    last_tick: Option<Instant>,
+    pub config: Config,
+    pub sinks: Sinks,
 }
 
 impl App {
-    pub fn new() -> (App, Sender<ArpPacket>) {
-        let (tx, rx) = mpsc::channel();
+    /// Events go to `sinks`, the ones of the config when watching live and none
+    /// when working offline, past events must not page anybody
+    pub fn new(
+        ifaces: &[String],
+        config: Config,
+        sinks: Sinks,
+    ) -> (App, BoundedSender<CapturedPacket>) {
+        let capture = &config.capture;
+        let (tx, rx) = queue::bounded(capture.queue, capture.overflow, capture.sample);
+        let (probe_tx, probe_rx) = mpsc::channel();
+        let (progress_tx, progress_rx) = mpsc::channel();
         (
             App {
-                list: StateList::new(),
-                changement_list: StateList::new(),
+                devices: DeviceStore::new(),
+                selected: None,
+                changement_list: StateList::with_capacity(config.history.changes),
+                probe_list: StateList::with_capacity(config.history.probes),
+                alerts: StateList::with_capacity(config.history.alerts),
+                stats: HashMap::new(),
+                bindings: HashMap::new(),
+                pending: HashMap::new(),
                 rx,
+                probe_tx,
+                probe_rx,
+                ifaces: ifaces.to_vec(),
+                scanners: HashMap::new(),
+                progress_tx,
+                progress_rx,
+                scans: HashMap::new(),
+                kernel: HashMap::new(),
+                kernel_rx: None,
+                kernel_reported: HashMap::new(),
+                defense: None,
+                control: None,
+                view: None,
                 scroll: 0,
                 mode: InputMode::NormalMode,
                 popup: Popup::None,
                 input: String::new(),
+                status: String::new(),
                 arp_frame_counter: 0,
+                counters: Counters::default(),
+                metrics: None,
+                last_tick: None,
+                config,
+                sinks,
             },
             tx,
         )
     }
 
+    /// Reads the config again from where it was loaded, the device table is kept.
+    /// Files of the sinks are opened again, for log rotation.
+    /// Returns the changed sections that only apply after a restart.
+    pub fn reload_config(&mut self) -> Result<Vec<&'static str>, Box<dyn Error>> {
+        self.sinks.reopen();
+        let mut config = Config::load(self.config.source.as_deref())?;
+        self.sinks.reconfigure(&config.sinks)?;
+        let pending = config.keep_startup_sections(&self.config);
+        self.config = config;
+        Ok(pending)
+    }
+
     pub fn scroll(&self) -> usize {
         self.scroll
     }
 
     pub fn set_scroll(&mut self, to: usize) {
-        if to > self.list.items.len() {
-            self.scroll = self.list.items.len() - 1;
-        } else {
-            self.scroll = to
+        self.scroll = to.min(self.visible_len().saturating_sub(1));
+    }
+
+    /// Scrolls just enough for the selected row to be among the `height` displayed
+    pub fn scroll_to_selected(&mut self, height: usize) {
+        let Some(selected) = self.selected else {
+            return;
+        };
+        if selected < self.scroll {
+            self.scroll = selected;
+        } else if height > 0 && selected >= self.scroll + height {
+            self.scroll = selected + 1 - height;
+        }
+    }
+
+    pub fn viewed_iface(&self) -> Option<&String> {
+        self.view.and_then(|i| self.ifaces.get(i))
+    }
+
+    /// Cycles between every interface and the combined view
+    pub fn next_view(&mut self) {
+        self.view = match self.view {
+            None if !self.ifaces.is_empty() => Some(0),
+            Some(i) if i + 1 < self.ifaces.len() => Some(i + 1),
+            _ => None,
+        };
+        self.selected = None;
+        self.scroll = 0;
+    }
+
+    pub fn is_viewed(&self, iface: &str) -> bool {
+        match self.viewed_iface() {
+            Some(viewed) => viewed == iface,
+            None => true,
+        }
+    }
+
+    /// Number of devices of the viewed interface
+    pub fn visible_len(&self) -> usize {
+        self.devices.count(self.viewed_iface().map(String::as_str))
+    }
+
+    /// Devices of the viewed interface displayed from the scroll position
+    pub fn visible_devices(&self, height: usize) -> Vec<&Device> {
+        let iface = self.viewed_iface().map(String::as_str);
+        self.devices.window(iface, self.scroll, height)
+    }
+
+    pub fn next_device(&mut self) {
+        let len = self.visible_len();
+        if len == 0 {
+            return;
+        }
+        self.selected = Some(match self.selected {
+            Some(i) => (i + 1).min(len - 1),
+            None => 0,
+        });
+    }
+
+    pub fn prev_device(&mut self) {
+        if self.visible_len() == 0 {
+            return;
+        }
+        self.selected = Some(match self.selected {
+            Some(i) => i.saturating_sub(1),
+            None => 0,
+        });
+    }
+
+    pub fn selected_device(&self) -> Option<&Device> {
+        let iface = self.viewed_iface().map(String::as_str);
+        self.devices.nth(iface, self.selected?)
+    }
+
+    /// Sends the command to the scanner of the interface, or of every one.
+    /// Returns the scanners reached.
+    pub fn scan_command(&self, iface: Option<&str>, command: ScanCommand) -> usize {
+        self.scanners
+            .iter()
+            .filter(|(name, _)| iface.map(|iface| iface == name.as_str()).unwrap_or(true))
+            .filter(|(_, scanner)| scanner.send(command.clone()).is_ok())
+            .count()
+    }
+
+    /// Keeps the last progress of each scanner, returns true when some was reported
+    pub fn poll_scans(&mut self) -> bool {
+        let mut updated = false;
+        for progress in self.progress_rx.try_iter() {
+            self.scans.insert(progress.iface.clone(), progress);
+            updated = true;
         }
+        updated
+    }
+
+    pub fn open_popup(&mut self, popup: Popup) {
+        self.popup = popup;
+        self.mode = InputMode::EditMode;
+        self.input.clear();
+    }
+
+    pub fn close_popup(&mut self) {
+        self.popup = Popup::None;
+        self.mode = InputMode::NormalMode;
     }
 }
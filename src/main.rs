@@ -1,27 +1,673 @@
-use std::{env::args, thread};
+use std::{
+    env,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
 
-use arp_watch::{sniff::sniff, ui, App};
-use log::{debug, error, info};
+use arp_watch::{
+    config::Config,
+    control,
+    daemon::run_daemon,
+    defense::Defense,
+    kernel::{self, NeighState, Neighbour},
+    metrics,
+    pcap::replay,
+    probe::probe,
+    report::{write_devices, Format},
+    sinks::{json::JsonSink, Sinks},
+    sniff::{listen, scan, sniff, ScanOptions},
+    structs::{
+        alert::{Alert, AlertKind},
+        arp::CapturedPacket,
+        event::{Event, EventKind},
+        net::{Device, IpAddr, IpRange, MacAddr},
+        queue::BoundedSender,
+    },
+    ui, App,
+};
+use clap::{Args, Parser, Subcommand};
+use env_logger::{Env, Target};
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+
+// Longest wait for a packet before checking whether to stop
+const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Parser)]
+#[command(
+    name = "arp-watch",
+    version,
+    about = "Watch ARP traffic for binding changes"
+)]
+struct Cli {
+    /// Config file, instead of the system-wide and per-user ones
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// Append logs to this file instead of writing them to stderr, the TUI
+    /// logs to $XDG_STATE_HOME/arp-watch/arp-watch.log by default
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Watch the interfaces in the TUI
+    Watch {
+        #[command(flatten)]
+        interfaces: InterfaceArgs,
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// Watch the interfaces without UI, writing one json object per event
+    Daemon {
+        #[command(flatten)]
+        interfaces: InterfaceArgs,
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// Append events to this file instead of writing them to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Sweep the ranges once and print the devices that answered
+    Scan {
+        #[command(flatten)]
+        interfaces: InterfaceArgs,
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// Seconds to wait for late replies after the sweep
+        #[arg(long, default_value_t = 2)]
+        wait: u64,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Check addresses are free (RFC 5227), exits with 1 on conflict and 2 on error
+    Probe {
+        #[arg(short, long)]
+        interface: String,
+        /// Ip, first-last range or cidr to probe
+        target: IpRange,
+    },
+    /// Run a capture file through the detection pipeline and print what it found
+    Replay {
+        /// Capture file, in pcap format
+        file: PathBuf,
+        /// Interface name the capture is tagged with
+        #[arg(long, default_value = "replay")]
+        interface: String,
+    },
+    /// Export the device table built from a capture file or by listening to interfaces
+    Export {
+        /// Capture file, in pcap format
+        #[arg(
+            long,
+            required_unless_present = "interfaces",
+            conflicts_with = "interfaces"
+        )]
+        pcap: Option<PathBuf>,
+        /// Interfaces to listen to, without sending anything
+        #[arg(short, long = "interface", value_delimiter = ',')]
+        interfaces: Vec<String>,
+        /// Seconds to listen for
+        #[arg(long, default_value_t = 60)]
+        duration: u64,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Send a sample alert to every configured sink
+    TestSinks,
+    /// Install permanent kernel ARP entries for the protected hosts
+    Pin {
+        #[arg(short, long)]
+        interface: String,
+        /// Control socket of the instance whose bindings give the macs missing
+        /// from the config, the one of the config by default
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+    },
+    /// Remove the permanent kernel ARP entries of the protected hosts
+    Unpin {
+        #[arg(short, long)]
+        interface: String,
+    },
+    /// Send a request to a running instance through its control socket
+    Ctl {
+        /// Control socket, the one of the config by default
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+        /// Request as json, e.g. '{"cmd": "lookup", "ip": "192.168.1.1"}'
+        request: String,
+    },
+}
+
+#[derive(Args)]
+struct InterfaceArgs {
+    /// Interface to use, repeat it or separate them with commas for several
+    #[arg(short, long = "interface", required = true, value_delimiter = ',')]
+    interfaces: Vec<String>,
+}
+
+#[derive(Args)]
+struct ScanArgs {
+    /// Range to sweep (ip, first-last or cidr), the interface networks by default
+    #[arg(short, long = "range", value_name = "RANGE")]
+    ranges: Vec<IpRange>,
+    /// Requests sent per second [default: 10]
+    #[arg(long, value_parser = parse_rate)]
+    rate: Option<f64>,
+}
+
+impl ScanArgs {
+    fn options(&self, config: &Config) -> Result<ScanOptions, Box<dyn Error>> {
+        let options = config.scan_options(&self.ranges, self.rate);
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// NaN, infinite or non-positive rates would leave the scan unpaced
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err("must be a positive number".to_owned());
+    }
+    Ok(rate)
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Output format: text, json or csv
+    #[arg(short, long, default_value_t = Format::Text)]
+    format: Format,
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    fn writer(&self) -> Result<Box<dyn Write>, Box<dyn Error>> {
+        match self.output {
+            Some(ref path) => Ok(Box::new(File::create(path)?)),
+            None => Ok(Box::new(io::stdout())),
+        }
+    }
+}
 
 fn main() {
-    std::env::set_var("RUST_LOG", "debug");
     std::env::set_var("RUST_BACKTRACE", "1");
-    env_logger::init();
+    let cli = Cli::parse();
+    // The TUI owns the terminal, anything written to stderr would garble it
+    let log_file = match cli.command {
+        Command::Watch { .. } => Some(cli.log_file.clone().unwrap_or_else(default_log_file)),
+        _ => cli.log_file.clone(),
+    };
+    // RUST_LOG=debug for more
+    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    if let Some(ref path) = log_file {
+        match open_log(path) {
+            Ok(file) => {
+                logger.target(Target::Pipe(Box::new(file)));
+            }
+            Err(e) => {
+                eprintln!("Can't open log file {}: {}", path.display(), e);
+                exit(2);
+            }
+        }
+    }
+    logger.init();
     debug!("Logger initialised");
 
-    let mut args = args();
-    if args.len() < 2 {
-        error!("Usage: arpwatch <iface> [Ip mask]");
-        return;
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            exit(2);
+        }
+    };
+    let res = match cli.command {
+        // Options are checked before the TUI or any scanner starts
+        Command::Watch { interfaces, scan } => scan
+            .options(&config)
+            .and_then(|options| watch(interfaces.interfaces, options, config)),
+        Command::Daemon {
+            interfaces,
+            scan,
+            output,
+        } => scan
+            .options(&config)
+            .and_then(|options| daemon(interfaces.interfaces, options, output, config)),
+        Command::Scan {
+            interfaces,
+            scan,
+            wait,
+            output,
+        } => scan
+            .options(&config)
+            .and_then(|options| run_scan(interfaces.interfaces, options, wait, &output, config)),
+        Command::Probe { interface, target } => exit(run_probe(&interface, &target)),
+        Command::Replay { file, interface } => run_replay(&file, &interface, config),
+        Command::Export {
+            pcap,
+            interfaces,
+            duration,
+            output,
+        } => export(pcap, interfaces, duration, &output, config),
+        Command::TestSinks => test_sinks(config),
+        Command::Pin { interface, socket } => {
+            let socket = socket.or(config.control.socket.clone());
+            pin(&interface, socket.as_deref(), &config)
+        }
+        Command::Unpin { interface } => unpin(&interface, &config),
+        Command::Ctl { socket, request } => ctl(socket.or(config.control.socket), &request),
+    };
+    if let Err(e) = res {
+        error!("{}", e);
+        if log_file.is_some() {
+            eprintln!("{}", e);
+        }
+        exit(2);
+    }
+}
+
+fn default_log_file() -> PathBuf {
+    let dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .unwrap_or_else(env::temp_dir);
+    dir.join("arp-watch").join("arp-watch.log")
+}
+
+fn open_log(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn watch(
+    ifacenames: Vec<String>,
+    options: ScanOptions,
+    config: Config,
+) -> Result<(), Box<dyn Error>> {
+    let sinks = Sinks::without_stdout(&config.sinks)?;
+    let (mut app, app_tx) = App::new(&ifacenames, config, sinks);
+    serve(&mut app)?;
+    follow_kernel(&mut app);
+    app.defense = Some(Defense::default());
+    spawn_sniffers(&mut app, options, app_tx);
+    ui::start_ui(app)
+}
+
+fn daemon(
+    ifacenames: Vec<String>,
+    options: ScanOptions,
+    output: Option<PathBuf>,
+    config: Config,
+) -> Result<(), Box<dyn Error>> {
+    let sinks = Sinks::new(&config.sinks)?;
+    let (mut app, app_tx) = App::new(&ifacenames, config, sinks);
+    let sink = match output {
+        Some(path) => JsonSink::file(&path)?,
+        None => JsonSink::stdout(),
+    };
+    app.sinks.pin(Box::new(sink));
+    serve(&mut app)?;
+    follow_kernel(&mut app);
+    app.defense = Some(Defense::default());
+    spawn_sniffers(&mut app, options, app_tx);
+    run_daemon(app)
+}
+
+/// Starts the metrics endpoint and the control socket when configured
+fn serve(app: &mut App) -> Result<(), Box<dyn Error>> {
+    if let Some(ref listen) = app.config.metrics.listen {
+        app.metrics = Some(metrics::serve(listen)?);
+    }
+    if let Some(ref path) = app.config.control.socket {
+        let (control, subscribers) = control::serve(path)?;
+        app.control = Some(control);
+        app.sinks.pin(Box::new(subscribers));
+    }
+    Ok(())
+}
+
+/// Follows the kernel neighbour table, to compare the host's cache with the wire
+fn follow_kernel(app: &mut App) {
+    match kernel::watch() {
+        Ok(kernel_rx) => app.kernel_rx = Some(kernel_rx),
+        Err(e) => warn!("Can't follow the kernel neighbour table: {}", e),
+    }
+}
+
+/// One capture thread per interface, all feeding the same app
+fn spawn_sniffers(app: &mut App, options: ScanOptions, app_tx: BoundedSender<CapturedPacket>) {
+    for ifacename in app.ifaces.clone() {
+        let app_tx = app_tx.clone();
+        let options = options.clone();
+        let progress_tx = app.progress_tx.clone();
+        let (scanner_tx, scanner_rx) = mpsc::channel();
+        app.scanners.insert(ifacename.clone(), scanner_tx);
+        thread::spawn(move || {
+            if let Err(e) = sniff(ifacename.as_str(), app_tx, options, scanner_rx, progress_tx) {
+                error!("Can't watch {}: {}", ifacename, e);
+            }
+        });
+    }
+}
+
+fn run_scan(
+    ifacenames: Vec<String>,
+    options: ScanOptions,
+    wait: u64,
+    output: &OutputArgs,
+    config: Config,
+) -> Result<(), Box<dyn Error>> {
+    let (mut app, app_tx) = App::new(&ifacenames, config, Sinks::default());
+    let handles: Vec<_> = ifacenames
+        .into_iter()
+        .map(|ifacename| {
+            let app_tx = app_tx.clone();
+            let options = options.clone();
+            thread::spawn(move || {
+                scan(ifacename.as_str(), app_tx, options)
+                    .map_err(|e| format!("Can't scan {}: {}", ifacename, e))
+            })
+        })
+        .collect();
+    drain(&mut app, || {
+        handles.iter().all(|handle| handle.is_finished())
+    });
+    for handle in handles {
+        handle.join().map_err(|_| "Scan thread panicked")??;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(wait);
+    drain(&mut app, || Instant::now() >= deadline);
+    let devices: Vec<Device> = app.devices.iter().cloned().collect();
+    write_devices(&mut *output.writer()?, &devices, output.format)
+}
+
+/// Handles the captured packets as they come until `done`, left waiting they
+/// would overflow the capture queue
+fn drain(app: &mut App, done: impl Fn() -> bool) {
+    while !done() {
+        match app.rx.recv_timeout(DRAIN_INTERVAL) {
+            Ok(capture) => app.handle_packet(capture),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    while let Ok(capture) = app.rx.try_recv() {
+        app.handle_packet(capture);
+    }
+    if app.rx.dropped() > 0 {
+        warn!(
+            "{} packets dropped, the capture queue was full",
+            app.rx.dropped()
+        );
+    }
+}
+
+/// Exit code is 0 when every address is free, 1 on conflict and 2 on error
+fn run_probe(ifacename: &str, range: &IpRange) -> i32 {
+    match probe(ifacename, range) {
+        Ok(report) => {
+            println!("{}", report);
+            if report.is_free() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            error!("Probe failed: {}", e);
+            2
+        }
+    }
+}
+
+fn run_replay(file: &Path, ifacename: &str, config: Config) -> Result<(), Box<dyn Error>> {
+    let (mut app, _) = App::new(&[ifacename.to_owned()], config, Sinks::default());
+    let count = replay(file, ifacename, &mut app)?;
+    info!("Replayed {} ARP frames", count);
+
+    let mut out = io::stdout();
+    writeln!(out, "Devices:")?;
+    let devices: Vec<Device> = app.devices.iter().cloned().collect();
+    write_devices(&mut out, &devices, Format::Text)?;
+    writeln!(out, "Changes:")?;
+    for (previous, dev) in app.changement_list.items.iter() {
+        writeln!(out, "{} -> {}", previous, dev)?;
+    }
+    writeln!(out, "Alerts:")?;
+    for alert in app.alerts.items.iter() {
+        writeln!(out, "{}", alert)?;
+    }
+    Ok(())
+}
+
+fn export(
+    pcap: Option<PathBuf>,
+    ifacenames: Vec<String>,
+    duration: u64,
+    output: &OutputArgs,
+    config: Config,
+) -> Result<(), Box<dyn Error>> {
+    let app = match pcap {
+        Some(file) => {
+            let (mut app, _) = App::new(&["replay".to_owned()], config, Sinks::default());
+            replay(&file, "replay", &mut app)?;
+            app
+        }
+        None => {
+            let (mut app, app_tx) = App::new(&ifacenames, config, Sinks::default());
+            for ifacename in ifacenames {
+                let app_tx = app_tx.clone();
+                thread::spawn(move || {
+                    if let Err(e) = listen(ifacename.as_str(), app_tx) {
+                        error!("Can't listen to {}: {}", ifacename, e);
+                    }
+                });
+            }
+            let deadline = Instant::now() + Duration::from_secs(duration);
+            drain(&mut app, || Instant::now() >= deadline);
+            app
+        }
+    };
+    let mut devices: Vec<Device> = app.devices.iter().cloned().collect();
+    devices.sort_by_key(|dev| dev.ip);
+    write_devices(&mut *output.writer()?, &devices, output.format)
+}
+
+/// Pins every protected host to its configured mac, or to the one a running
+/// instance verified and the kernel agrees with
+fn pin(iface: &str, socket: Option<&Path>, config: &Config) -> Result<(), Box<dyn Error>> {
+    if config.protected.is_empty() {
+        return Err("No protected host configured".into());
+    }
+    let entries = kernel::read_proc(Path::new(kernel::PROC_ARP))?;
+    let mut failed = 0;
+    for host in config.protected.iter() {
+        let mac = match (host.mac, socket) {
+            (Some(mac), _) => Ok(mac),
+            (None, Some(socket)) => lookup_binding(socket, iface, &host.ip).and_then(|answer| {
+                let cached = entries
+                    .iter()
+                    .find(|entry| entry.iface == iface && entry.ip == host.ip);
+                verified_mac(&answer, cached)
+            }),
+            (None, None) => Err("no mac configured and no control socket to look it up".into()),
+        };
+        let res = mac.and_then(|mac| {
+            kernel::pin(iface, &host.ip, &mac)?;
+            Ok(mac)
+        });
+        match res {
+            Ok(mac) => println!("{} pinned to {} on {}", host.ip, mac, iface),
+            Err(e) => {
+                error!("Can't pin {}: {}", host.ip, e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} host(s) not pinned", failed).into()),
+    }
+}
+
+/// Answer of a running instance about the binding of the ip
+fn lookup_binding(socket: &Path, iface: &str, ip: &IpAddr) -> Result<Value, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Can't connect to {}: {}", socket.display(), e))?;
+    writeln!(
+        stream,
+        "{}",
+        json!({ "cmd": "binding", "iface": iface, "ip": ip })
+    )?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    let answer: Value = serde_json::from_str(&answer)?;
+    if answer["ok"] != true {
+        return Err(format!("lookup failed: {}", answer["error"]).into());
+    }
+    Ok(answer)
+}
+
+/// Mac of a binding fit for a permanent entry: a spoofer running for a few
+/// seconds gets its binding settled, not verified, and the kernel entry must
+/// agree so that a poisoning predating the instance isn't made permanent
+fn verified_mac(answer: &Value, cached: Option<&Neighbour>) -> Result<MacAddr, Box<dyn Error>> {
+    if answer["verified"] != true {
+        return Err("binding changed or too recent to be verified, configure the mac".into());
+    }
+    let mac: MacAddr = answer["mac"]
+        .as_str()
+        .ok_or("lookup answer without mac")?
+        .parse()?;
+    let cached = cached
+        .filter(|entry| entry.state.resolved())
+        .and_then(|entry| entry.mac);
+    match cached {
+        Some(cached) if cached == mac => Ok(mac),
+        Some(cached) => Err(format!("kernel has {} where the wire has {}", cached, mac).into()),
+        None => Err("no kernel entry to confirm the mac, reach the host first".into()),
+    }
+}
+
+/// Removes the permanent entries of the protected hosts, dynamic ones are left alone
+fn unpin(iface: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let entries = kernel::read_proc(Path::new(kernel::PROC_ARP))?;
+    for host in config.protected.iter() {
+        let pinned = entries.iter().any(|entry| {
+            entry.iface == iface && entry.ip == host.ip && entry.state == NeighState::Permanent
+        });
+        if !pinned {
+            println!("{} not pinned on {}", host.ip, iface);
+            continue;
+        }
+        kernel::unpin(iface, &host.ip).map_err(|e| format!("Can't unpin {}: {}", host.ip, e))?;
+        println!("{} unpinned on {}", host.ip, iface);
+    }
+    Ok(())
+}
+
+fn test_sinks(config: Config) -> Result<(), Box<dyn Error>> {
+    if config.sinks.is_empty() {
+        return Err("No sink configured".into());
+    }
+    let mut sinks = Sinks::new(&config.sinks)?;
+    // Documentation addresses, nobody can mistake it for a real alert
+    let device = Device::new(&[0x02, 0, 0, 0, 0, 0x02], &[192, 0, 2, 1], "test")?;
+    let previous = Device::new(&[0x02, 0, 0, 0, 0, 0x01], &[192, 0, 2, 1], "test")?;
+    let alert = Alert::new(AlertKind::GratuitousConflict, device, Some(previous));
+    sinks.emit(&Event::new(EventKind::Alert(alert)));
+    sinks.flush();
+    info!("Sample alert sent to {} sinks", config.sinks.len());
+    Ok(())
+}
+
+/// Prints the answer, then the events for subscriptions
+fn ctl(socket: Option<PathBuf>, request: &str) -> Result<(), Box<dyn Error>> {
+    let socket = socket.ok_or("No control socket configured")?;
+    let mut stream = UnixStream::connect(&socket)
+        .map_err(|e| format!("Can't connect to {}: {}", socket.display(), e))?;
+    writeln!(stream, "{}", request.trim())?;
+    let subscribed = matches!(
+        serde_json::from_str(request),
+        Ok(control::Request::Subscribe)
+    );
+    let mut out = io::stdout();
+    for line in BufReader::new(stream).lines() {
+        writeln!(out, "{}", line?)?;
+        if !subscribed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn neighbour(mac: &str, state: NeighState) -> Neighbour {
+        Neighbour {
+            iface: "eth0".to_owned(),
+            ip: "192.168.1.1".parse().unwrap(),
+            mac: Some(mac.parse().unwrap()),
+            state,
+        }
+    }
+
+    #[test]
+    fn invalid_rate_is_a_usage_error() {
+        for rate in ["0", "-1", "NaN", "inf", "fast"] {
+            let args = ["arp-watch", "watch", "-i", "eth0", "--rate", rate];
+            assert!(Cli::try_parse_from(args).is_err(), "{}", rate);
+        }
+        let args = ["arp-watch", "watch", "-i", "eth0", "--rate", "2.5"];
+        assert!(Cli::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn verified_mac_agreeing_with_kernel() {
+        let answer = json!({ "ok": true, "mac": "02:00:00:00:00:01", "verified": true });
+        let cached = neighbour("02:00:00:00:00:01", NeighState::Reachable);
+        let mac = verified_mac(&answer, Some(&cached)).unwrap();
+        assert_eq!(mac, "02:00:00:00:00:01".parse().unwrap());
+    }
+
+    #[test]
+    fn unverified_binding_refused() {
+        let cached = neighbour("02:00:00:00:00:66", NeighState::Reachable);
+        // Settled by a spoofer, not verified
+        for answer in [
+            json!({ "ok": true, "mac": "02:00:00:00:00:66", "settled": true, "verified": false }),
+            json!({ "ok": true, "mac": "02:00:00:00:00:66", "settled": true }),
+        ] {
+            assert!(verified_mac(&answer, Some(&cached)).is_err());
+        }
     }
 
-    let ifacename = args.next().unwrap().clone();
-    let (app, app_tx) = App::new();
-    info!("{}", args.len());
-    if args.len() < 3 {
-        thread::spawn(move || sniff(ifacename.as_str(), Some(app_tx)));
-        ui::start_ui(app).unwrap();
-    } else {
-        sniff(ifacename.as_str(), None);
+    #[test]
+    fn kernel_disagreement_refused() {
+        let answer = json!({ "ok": true, "mac": "02:00:00:00:00:01", "verified": true });
+        for cached in [
+            Some(neighbour("02:00:00:00:00:66", NeighState::Stale)),
+            Some(neighbour("02:00:00:00:00:01", NeighState::Failed)),
+            None,
+        ] {
+            assert!(verified_mac(&answer, cached.as_ref()).is_err());
+        }
     }
 }
@@ -0,0 +1,348 @@
+use log::{error, info, warn};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{structs::alert::AlertKind, App};
+
+// Counted by the capture and probing threads, which don't own the app
+pub static PARSE_ERRORS: AtomicU64 = AtomicU64::new(0);
+pub static PROBES_SENT: AtomicU64 = AtomicU64::new(0);
+pub static CORRECTIVE_SENT: AtomicU64 = AtomicU64::new(0);
+
+// Clients stalling longer are dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+// Connections answered at the same time, a slow client doesn't hold the others
+const MAX_CONNECTIONS: usize = 16;
+
+/// Counters kept by the detection pipeline, on top of `App::arp_frame_counter`
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub requests: u64,
+    pub replies: u64,
+    pub alerts: HashMap<AlertKind, u64>,
+}
+
+/// Last rendered metrics, refreshed by the app on every tick
+pub type Snapshot = Arc<Mutex<String>>;
+
+/// Serves the snapshot on GET /metrics, in the Prometheus text format
+pub fn serve(addr: &str) -> Result<Snapshot, Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    let snapshot = Snapshot::default();
+    accept(listener, snapshot.clone());
+    Ok(snapshot)
+}
+
+/// Answers the connections in the background, each on its own thread
+fn accept(listener: TcpListener, served: Snapshot) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Metrics request failed: {}", e);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::Relaxed);
+                warn!("Too many metrics connections, dropping one");
+                continue;
+            }
+            let served = served.clone();
+            let connections = connections.clone();
+            thread::spawn(move || {
+                if let Err(e) = answer(stream, &served) {
+                    error!("Metrics request failed: {}", e);
+                }
+                connections.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+}
+
+fn answer(mut stream: TcpStream, snapshot: &Snapshot) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Headers are not needed, but must be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, body) = match request.split_whitespace().collect::<Vec<&str>>()[..] {
+        ["GET", "/metrics", _] => (
+            "200 OK",
+            snapshot.lock().map(|body| body.clone()).unwrap_or_default(),
+        ),
+        ["GET", _, _] => ("404 Not Found", "Not found\n".to_owned()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Current metrics of the app, in the Prometheus text format
+pub fn render(app: &App) -> String {
+    let mut out = String::new();
+    let gone = app
+        .devices
+        .iter()
+        .filter(|dev| {
+            app.stats
+                .get(&dev.key())
+                .map(|stats| stats.gone)
+                .unwrap_or(false)
+        })
+        .count();
+
+    metric(
+        &mut out,
+        "arpwatch_frames_total",
+        "counter",
+        "ARP frames captured",
+        &[("", app.arp_frame_counter as u64)],
+    );
+    metric(
+        &mut out,
+        "arpwatch_frames_by_opcode_total",
+        "counter",
+        "ARP frames captured by operation",
+        &[
+            ("opcode=\"request\"", app.counters.requests),
+            ("opcode=\"reply\"", app.counters.replies),
+        ],
+    );
+    metric(
+        &mut out,
+        "arpwatch_frames_dropped_total",
+        "counter",
+        "ARP frames dropped because the processing queue was full",
+        &[("", app.rx.dropped())],
+    );
+    metric(
+        &mut out,
+        "arpwatch_queue_length",
+        "gauge",
+        "ARP frames waiting to be processed",
+        &[("", app.rx.len() as u64)],
+    );
+    metric(
+        &mut out,
+        "arpwatch_parse_errors_total",
+        "counter",
+        "ARP frames that could not be parsed",
+        &[("", PARSE_ERRORS.load(Ordering::Relaxed))],
+    );
+    metric(
+        &mut out,
+        "arpwatch_probes_sent_total",
+        "counter",
+        "ARP requests sent by scans and probes",
+        &[("", PROBES_SENT.load(Ordering::Relaxed))],
+    );
+    metric(
+        &mut out,
+        "arpwatch_corrective_frames_total",
+        "counter",
+        "Gratuitous replies sent to restore protected hosts",
+        &[("", CORRECTIVE_SENT.load(Ordering::Relaxed))],
+    );
+    metric(
+        &mut out,
+        "arpwatch_devices_known",
+        "gauge",
+        "Devices in the table",
+        &[("", app.devices.len() as u64)],
+    );
+    metric(
+        &mut out,
+        "arpwatch_devices_active",
+        "gauge",
+        "Devices seen recently",
+        &[("", (app.devices.len() - gone) as u64)],
+    );
+    metric(
+        &mut out,
+        "arpwatch_devices_stale",
+        "gauge",
+        "Devices reported gone",
+        &[("", gone as u64)],
+    );
+    let alerts: Vec<(String, u64)> = AlertKind::ALL
+        .iter()
+        .map(|kind| {
+            (
+                format!("kind=\"{}\"", kind.name()),
+                app.counters.alerts.get(kind).copied().unwrap_or(0),
+            )
+        })
+        .collect();
+    let alerts: Vec<(&str, u64)> = alerts
+        .iter()
+        .map(|(labels, value)| (labels.as_str(), *value))
+        .collect();
+    metric(
+        &mut out,
+        "arpwatch_alerts_total",
+        "counter",
+        "Alerts raised by kind",
+        &alerts,
+    );
+    out
+}
+
+/// Writes the help and type lines then one sample per label set
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        testing::{self, device},
+    };
+    use std::io::Read;
+
+    fn app() -> App {
+        let mut app = testing::app(Config::default());
+        for last in 1..=3 {
+            let mac = format!("02:00:00:00:00:0{}", last);
+            let ip = format!("10.0.0.{}", last);
+            app.devices.upsert(device(&mac, &ip, "eth0"));
+        }
+        let gone = app.devices.iter().next().unwrap().key();
+        app.stats.entry(gone).or_default().gone = true;
+        app.arp_frame_counter = 12;
+        app.counters.requests = 7;
+        app.counters.replies = 5;
+        app.counters.alerts.insert(AlertKind::FlipFlop, 2);
+        app
+    }
+
+    #[test]
+    fn rendered() {
+        let out = render(&app());
+        for expected in [
+            "# HELP arpwatch_frames_total ARP frames captured\n\
+             # TYPE arpwatch_frames_total counter\n\
+             arpwatch_frames_total 12\n",
+            "arpwatch_frames_by_opcode_total{opcode=\"request\"} 7\n\
+             arpwatch_frames_by_opcode_total{opcode=\"reply\"} 5\n",
+            "arpwatch_devices_known 3\n",
+            "arpwatch_devices_active 2\n",
+            "arpwatch_devices_stale 1\n",
+            "arpwatch_alerts_total{kind=\"flip-flop\"} 2\n",
+            "arpwatch_alerts_total{kind=\"gratuitous-storm\"} 0\n",
+        ] {
+            assert!(out.contains(expected), "{}\n{}", expected, out);
+        }
+        // One sample per alert kind, each family described once
+        let alerts = out
+            .lines()
+            .filter(|line| line.starts_with("arpwatch_alerts_total{"));
+        assert_eq!(alerts.count(), AlertKind::ALL.len());
+        assert_eq!(
+            out.matches("# TYPE ").count(),
+            out.matches("# HELP ").count()
+        );
+    }
+
+    fn served(body: &str) -> (std::net::SocketAddr, Snapshot) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let snapshot = Snapshot::default();
+        *snapshot.lock().unwrap() = body.to_owned();
+        accept(listener, snapshot.clone());
+        (addr, snapshot)
+    }
+
+    fn get(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn answered() {
+        let body = "arpwatch_frames_total 12\n";
+        let (addr, _snapshot) = served(body);
+        let response = get(addr, "GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n");
+        assert_eq!(
+            response,
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        );
+        let response = get(addr, "GET / HTTP/1.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("Content-Length: 10\r\nConnection: close\r\n\r\nNot found\n"));
+        let response = get(addr, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn connections_capped() {
+        let (addr, _snapshot) = served("");
+        // Clients sending nothing, each holding a thread until the timeout
+        let idle: Vec<TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let mut dropped = TcpStream::connect(addr).unwrap();
+        dropped
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buf = [0; 16];
+        // Closed right away rather than answered
+        assert_eq!(dropped.read(&mut buf).unwrap(), 0);
+        drop(idle);
+    }
+}
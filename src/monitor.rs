@@ -0,0 +1,373 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    metrics,
+    structs::{
+        alert::{Alert, AlertKind},
+        arp::{ARPOperation, ArpPacket, CapturedPacket},
+        binding::{Binding, Rebind},
+        event::{Event, EventKind},
+        net::{Device, MacAddr},
+        pending::PendingRequests,
+    },
+    App,
+};
+
+// More than GARP_STORM_THRESHOLD gratuitous ARPs within GARP_STORM_WINDOW is a storm
+pub(crate) const GARP_STORM_WINDOW: Duration = Duration::from_secs(10);
+pub(crate) const GARP_STORM_THRESHOLD: usize = 10;
+// Requests not answered within this delay are forgotten
+const PENDING_REQUEST_TTL: Duration = Duration::from_secs(3);
+// Devices silent for longer are reported gone
+const GONE_AFTER: Duration = Duration::from_secs(600);
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+// Minimum delay between two unsolicited reply alerts for the same device
+const UNSOLICITED_ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+impl App {
+    pub fn handle_packet(&mut self, capture: CapturedPacket) {
+        let CapturedPacket {
+            packet,
+            iface,
+            at,
+            local,
+        } = capture;
+        if !local {
+            self.arp_frame_counter += 1;
+            match packet.operation {
+                ARPOperation::Request => self.counters.requests += 1,
+                ARPOperation::Reply => self.counters.replies += 1,
+            }
+        }
+        self.tick(at);
+        let dev = Device {
+            mac: packet.sender_mac,
+            ip: packet.sender_ip,
+            iface,
+        };
+        if !local {
+            self.stats.entry(dev.key()).or_default().last_seen = Some(at);
+        }
+
+        if packet.is_gratuitous() {
+            self.handle_gratuitous(dev, at);
+            return;
+        }
+        match packet.operation {
+            ARPOperation::Request => {
+                // Caches learn the sender of requests too, a protected ip can be
+                // hijacked without a single reply
+                if self.config.protected(&dev.ip).is_some() {
+                    self.rebind(&dev, at);
+                    self.check_protected(&dev);
+                }
+                self.pending
+                    .entry(dev.iface)
+                    .or_insert_with(|| PendingRequests::new(PENDING_REQUEST_TTL))
+                    .insert(packet.target_ip, packet.sender_mac, at)
+            }
+            ARPOperation::Reply => {
+                self.correlate(&packet, &dev, at);
+                match self.rebind(&dev, at) {
+                    Some(Rebind::Changed(previous)) => {
+                        self.alert_rebind(AlertKind::ChangedEthernetAddress, &dev, previous)
+                    }
+                    Some(Rebind::FlipFlop(previous)) => {
+                        self.alert_rebind(AlertKind::FlipFlop, &dev, previous)
+                    }
+                    None => {}
+                }
+                self.learn(dev);
+            }
+        }
+    }
+
+    /// Expires outstanding requests and reports devices not seen for GONE_AFTER.
+    /// Called for every packet and by the event loops, runs at most once per TICK_INTERVAL.
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(last_tick) = self.last_tick {
+            if now.saturating_duration_since(last_tick) < TICK_INTERVAL {
+                return;
+            }
+        }
+        self.last_tick = Some(now);
+
+        for pending in self.pending.values_mut() {
+            pending.expire(now);
+        }
+        let mut gone = vec![];
+        for dev in self.devices.iter() {
+            if let Some(stats) = self.stats.get_mut(&dev.key()) {
+                let last_seen = stats.last_seen.unwrap_or(now);
+                if !stats.gone && now.saturating_duration_since(last_seen) > GONE_AFTER {
+                    stats.gone = true;
+                    gone.push(dev.clone());
+                }
+            }
+        }
+        for dev in gone {
+            self.emit(EventKind::Gone(dev));
+        }
+        if let Some(ref snapshot) = self.metrics {
+            let rendered = metrics::render(self);
+            if let Ok(mut snapshot) = snapshot.lock() {
+                *snapshot = rendered;
+            }
+        }
+    }
+
+    /// Matches a reply against the outstanding requests of its segment
+    fn correlate(&mut self, reply: &ArpPacket, dev: &Device, at: Instant) {
+        let stats = self.stats.entry(dev.key()).or_default();
+        let request = self
+            .pending
+            .get_mut(&dev.iface)
+            .and_then(|pending| pending.answer(&reply.sender_ip, &reply.target_mac));
+        match request {
+            Some(request) => stats.record_latency(at.saturating_duration_since(request.at)),
+            None => {
+                if stats.record_unsolicited(at, UNSOLICITED_ALERT_INTERVAL) {
+                    self.alert(Alert::new(AlertKind::UnsolicitedReply, dev.clone(), None));
+                }
+            }
+        }
+    }
+
+    fn handle_gratuitous(&mut self, dev: Device, at: Instant) {
+        let stats = self.stats.entry(dev.key()).or_default();
+        if stats.record_gratuitous(at, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD) {
+            self.alert(Alert::new(AlertKind::GratuitousStorm, dev.clone(), None));
+        }
+
+        if let Some(Rebind::Changed(previous) | Rebind::FlipFlop(previous)) = self.rebind(&dev, at)
+        {
+            self.alert_rebind(AlertKind::GratuitousConflict, &dev, previous);
+        }
+        self.learn(dev);
+    }
+
+    /// Binds the ip of the device to its mac, returns how the binding changed
+    fn rebind(&mut self, dev: &Device, at: Instant) -> Option<Rebind> {
+        let key = (dev.iface.clone(), dev.ip);
+        match self.bindings.get_mut(&key) {
+            Some(binding) => binding.rebind(&dev.mac, at),
+            None => {
+                self.bindings.insert(key, Binding::new(dev.mac, at));
+                None
+            }
+        }
+    }
+
+    fn alert_rebind(&mut self, kind: AlertKind, dev: &Device, previous: MacAddr) {
+        // Reported once, by check_protected, rather than twice to every sink
+        if self.config.protected(&dev.ip).is_some() {
+            return;
+        }
+        let previous = Device {
+            mac: previous,
+            ip: dev.ip,
+            iface: dev.iface.clone(),
+        };
+        let previous_seen = self.last_seen(&previous);
+        let mut alert = Alert::new(kind, dev.clone(), Some(previous));
+        alert.previous_seen = previous_seen;
+        self.alert(alert);
+    }
+
+    /// Wall clock time the device was last heard from
+    fn last_seen(&self, dev: &Device) -> Option<SystemTime> {
+        let last_seen = self.stats.get(&dev.key())?.last_seen?;
+        SystemTime::now().checked_sub(Instant::now().saturating_duration_since(last_seen))
+    }
+
+    /// Updates the binding of the device, recording the change if it moved
+    fn learn(&mut self, dev: Device) {
+        self.check_protected(&dev);
+        self.check_kernel(&dev.iface, &dev.ip);
+        if self.devices.get(&dev.key()).is_some() {
+            if let Some(previous) = self.devices.upsert(dev.clone()) {
+                self.emit(EventKind::Changed {
+                    previous,
+                    device: dev.clone(),
+                });
+            }
+            let stats = self.stats.entry(dev.key()).or_default();
+            if stats.gone {
+                stats.gone = false;
+                self.emit(EventKind::NewDevice(dev));
+            }
+            return;
+        }
+
+        let elsewhere = self
+            .devices
+            .find_by_mac(&dev.mac)
+            .find(|other| other.iface != dev.iface)
+            .cloned();
+        if let Some(elsewhere) = elsewhere {
+            self.alert(Alert::new(
+                AlertKind::MacOnMultipleSegments,
+                dev.clone(),
+                Some(elsewhere),
+            ));
+        }
+        self.devices.upsert(dev.clone());
+        self.emit(EventKind::NewDevice(dev));
+    }
+
+    /// Alerts when a protected ip gets bound to another mac than the configured one,
+    /// or than the one its binding had before this packet
+    fn check_protected(&mut self, dev: &Device) {
+        let Some(host) = self.config.protected(&dev.ip) else {
+            return;
+        };
+        // Validated to come with a mac
+        let restore = host.restore && host.mac.is_some();
+        // Bound by the caller, a single observation means this packet moved or created it
+        let Some(binding) = self.bindings.get(&(dev.iface.clone(), dev.ip)) else {
+            return;
+        };
+        let moved = binding.observations == 1;
+        let trusted = match (host.mac, binding.previous) {
+            (Some(mac), _) => mac,
+            (None, Some(previous)) if moved => previous,
+            (None, _) => return,
+        };
+        if trusted == dev.mac {
+            return;
+        }
+        let previous = Device {
+            mac: trusted,
+            ip: dev.ip,
+            iface: dev.iface.clone(),
+        };
+        // Only binding changes are alerted on, not every packet of the same binding
+        if moved {
+            self.alert(Alert::new(
+                AlertKind::ProtectedHostChanged,
+                dev.clone(),
+                Some(previous.clone()),
+            ));
+        }
+        // Every claim gets corrected, not only the first one, within the rate limit
+        if restore {
+            self.restore(previous);
+        }
+    }
+
+    /// Announces the legitimate binding of a hijacked host, when live
+    fn restore(&mut self, legitimate: Device) {
+        let Some(ref mut defense) = self.defense else {
+            return;
+        };
+        if defense.restore(&legitimate, &self.config.defense, Instant::now()) {
+            self.status = format!("Restoring {} at {}", legitimate.ip, legitimate.mac);
+        }
+    }
+
+    pub(crate) fn alert(&mut self, alert: Alert) {
+        self.emit(EventKind::Alert(alert));
+    }
+
+    fn emit(&mut self, kind: EventKind) {
+        let event = Event::new(kind);
+        self.sinks.emit(&event);
+        match event.kind {
+            EventKind::Changed { previous, device } => {
+                self.changement_list.push((previous, device))
+            }
+            EventKind::Alert(alert) => {
+                *self.counters.alerts.entry(alert.kind).or_default() += 1;
+                self.alerts.push(alert)
+            }
+            EventKind::NewDevice(_) | EventKind::Gone(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ProtectedHost, testing, Config};
+
+    const LEGIT: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const ATTACKER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x66]);
+
+    fn app(mac: Option<MacAddr>) -> App {
+        let config = Config {
+            protected: vec![ProtectedHost {
+                ip: "192.168.1.1".parse().unwrap(),
+                mac,
+                restore: false,
+            }],
+            ..Config::default()
+        };
+        testing::app(config)
+    }
+
+    fn capture(packet: ArpPacket, at: Instant) -> CapturedPacket {
+        CapturedPacket {
+            packet,
+            iface: "eth0".to_owned(),
+            at,
+            local: false,
+        }
+    }
+
+    fn protected_alerts(app: &App) -> Vec<(MacAddr, MacAddr)> {
+        app.alerts
+            .items
+            .iter()
+            .filter(|alert| alert.kind == AlertKind::ProtectedHostChanged)
+            .map(|alert| (alert.device.mac, alert.previous.as_ref().unwrap().mac))
+            .collect()
+    }
+
+    #[test]
+    fn protected_hijacked_by_requests() {
+        let host = "192.168.1.1".parse().unwrap();
+        let target = "192.168.1.2".parse().unwrap();
+        let start = Instant::now();
+        for mac in [Some(LEGIT), None] {
+            let mut app = app(mac);
+            app.handle_packet(capture(ArpPacket::request(LEGIT, host, target), start));
+            assert!(protected_alerts(&app).is_empty());
+            // Alerted once per change of binding, not for every request
+            for i in 1..4 {
+                let at = start + Duration::from_secs(i);
+                app.handle_packet(capture(ArpPacket::request(ATTACKER, host, target), at));
+            }
+            assert_eq!(protected_alerts(&app), vec![(ATTACKER, LEGIT)], "{:?}", mac);
+        }
+    }
+
+    #[test]
+    fn protected_hijacked_by_reply() {
+        let host = "192.168.1.1".parse().unwrap();
+        let target = "192.168.1.2".parse().unwrap();
+        let start = Instant::now();
+        let requester = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+        let mut app = app(Some(LEGIT));
+        // Both answer requests, not unsolicited
+        for (i, mac) in [LEGIT, ATTACKER].into_iter().enumerate() {
+            let at = start + Duration::from_secs(i as u64);
+            app.handle_packet(capture(ArpPacket::request(requester, target, host), at));
+            let reply = ArpPacket::reply(mac, host, requester, target);
+            app.handle_packet(capture(reply, at));
+        }
+        assert_eq!(app.alerts.items.len(), 1, "{:?}", app.alerts.items);
+        assert_eq!(protected_alerts(&app), vec![(ATTACKER, LEGIT)]);
+    }
+
+    #[test]
+    fn unprotected_requests_not_bound() {
+        let mut app = app(Some(LEGIT));
+        let sender = "192.168.1.3".parse().unwrap();
+        let target = "192.168.1.2".parse().unwrap();
+        app.handle_packet(capture(
+            ArpPacket::request(ATTACKER, sender, target),
+            Instant::now(),
+        ));
+        assert!(app.bindings.is_empty());
+    }
+}
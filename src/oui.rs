@@ -0,0 +1,81 @@
+use std::{collections::HashMap, fs};
+
+use crate::structs::net::MacAddr;
+
+/// Vendor databases shipped by distributions, the first one found is used
+const DATABASES: [&str; 5] = [
+    "/usr/share/ieee-data/oui.txt",
+    "/var/lib/ieee-data/oui.txt",
+    "/usr/share/misc/oui.txt",
+    "/usr/share/arpwatch/ethercodes.dat",
+    "/usr/share/nmap/nmap-mac-prefixes",
+];
+
+/// Vendor names by the first three bytes of the mac (OUI)
+#[derive(Debug, Default)]
+pub struct Vendors {
+    names: HashMap<[u8; 3], String>,
+}
+
+impl Vendors {
+    /// Empty when no database is installed, lookups then return None
+    pub fn load() -> Vendors {
+        DATABASES
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .map(|content| Vendors::parse(&content))
+            .unwrap_or_default()
+    }
+
+    /// Understands the IEEE oui.txt, arpwatch ethercodes.dat and nmap formats
+    pub fn parse(content: &str) -> Vendors {
+        let mut names = HashMap::new();
+        for line in content.lines() {
+            if let Some((oui, name)) = parse_line(line) {
+                names.entry(oui).or_insert(name);
+            }
+        }
+        Vendors { names }
+    }
+
+    pub fn get(&self, mac: &MacAddr) -> Option<&str> {
+        let oui = [mac.0[0], mac.0[1], mac.0[2]];
+        self.names.get(&oui).map(String::as_str)
+    }
+}
+
+fn parse_line(line: &str) -> Option<([u8; 3], String)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    // oui.txt: "00-00-0C   (hex)		Cisco Systems, Inc"
+    if let Some((prefix, name)) = line.split_once("(hex)") {
+        return Some((parse_oui(prefix.trim(), '-')?, name.trim().to_owned()));
+    }
+    let (prefix, name) = line.split_once(char::is_whitespace)?;
+    let oui = if prefix.contains(':') {
+        // ethercodes.dat: "0:0:c	Cisco"
+        parse_oui(prefix, ':')?
+    } else {
+        // nmap: "00000C Cisco Systems"
+        if prefix.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(prefix, 16).ok()?;
+        let bytes = value.to_be_bytes();
+        [bytes[1], bytes[2], bytes[3]]
+    };
+    Some((oui, name.trim().to_owned()))
+}
+
+fn parse_oui(prefix: &str, separator: char) -> Option<[u8; 3]> {
+    let bytes = prefix
+        .split(separator)
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    match bytes[..] {
+        [a, b, c] => Some([a, b, c]),
+        _ => None,
+    }
+}
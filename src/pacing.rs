@@ -0,0 +1,349 @@
+use log::debug;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::structs::{
+    arp::{ARPOperation, ArpPacket},
+    net::{IpAddr, MacAddr},
+};
+
+// Requests to known hosts between two adjustments of the rate in adaptive mode
+const ADAPTIVE_WINDOW: u64 = 20;
+// Delay after which a request to a known host counts as unanswered
+const REPLY_GRACE: Duration = Duration::from_secs(1);
+
+/// Replies addressed to the scanner, recorded by the capture thread
+#[derive(Debug)]
+pub struct Replies {
+    mac: MacAddr,
+    // Last reply of each address that answered
+    answered: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Replies {
+    pub fn new(mac: MacAddr) -> Replies {
+        Replies {
+            mac,
+            answered: Mutex::default(),
+        }
+    }
+
+    pub fn observe(&self, packet: &ArpPacket) {
+        if matches!(packet.operation, ARPOperation::Reply) && packet.target_mac == self.mac {
+            self.record(packet.sender_ip, Instant::now());
+        }
+    }
+
+    fn record(&self, ip: IpAddr, at: Instant) {
+        if let Ok(mut answered) = self.answered.lock() {
+            answered.insert(ip, at);
+        }
+    }
+
+    /// Whether the address ever answered the scanner
+    pub fn known(&self, ip: &IpAddr) -> bool {
+        self.answered
+            .lock()
+            .map(|answered| answered.contains_key(ip))
+            .unwrap_or(false)
+    }
+
+    pub fn answered_since(&self, ip: &IpAddr, at: Instant) -> bool {
+        self.answered
+            .lock()
+            .map(|answered| answered.get(ip).map(|last| *last >= at).unwrap_or(false))
+            .unwrap_or(false)
+    }
+}
+
+/**
+Paces the requests of a scan: a token bucket refilled at `rate` per second
+holding at most `burst` tokens, each wait randomized by up to `jitter`.
+
+In adaptive mode, only requests to hosts that answered before are accounted,
+addresses nobody uses staying silent whatever the load. The rate is halved,
+down to `min_rate`, after every window in which most of those hosts stopped
+answering, and doubled back up to `rate` after every window in which they did.
+*/
+pub struct Pacer {
+    max_rate: f64,
+    min_rate: f64,
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+    jitter: f64,
+    adaptive: bool,
+    // Requests to known hosts waiting for their reply, oldest first
+    outstanding: VecDeque<(IpAddr, Instant)>,
+    // Requests to known hosts accounted in the current window, and those answered
+    window_sent: u64,
+    window_answered: u64,
+    rng: u64,
+}
+
+impl Pacer {
+    pub fn new(
+        rate: f64,
+        burst: u32,
+        jitter: f64,
+        adaptive: bool,
+        min_rate: f64,
+    ) -> Result<Pacer, String> {
+        // NaN or infinite rates would leave the scan unpaced
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(format!("Invalid scan rate: {}", rate));
+        }
+        if !(min_rate.is_finite() && min_rate > 0.0) {
+            return Err(format!("Invalid minimum scan rate: {}", min_rate));
+        }
+        let burst = f64::from(burst.max(1));
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        Ok(Pacer {
+            max_rate: rate,
+            min_rate: min_rate.min(rate),
+            rate,
+            burst,
+            tokens: burst,
+            refilled: Instant::now(),
+            jitter: jitter.clamp(0.0, 1.0),
+            adaptive,
+            outstanding: VecDeque::new(),
+            window_sent: 0,
+            window_answered: 0,
+            // Spreads the bits of the clock, xorshift must not start from 0
+            rng: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        })
+    }
+
+    /// Blocks until the next request may be sent
+    pub fn acquire(&mut self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Takes a token at `now`, returns how long to wait for it when the bucket is empty
+    fn reserve(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        let mut wait = Duration::ZERO;
+        if self.tokens < 1.0 {
+            let missing = (1.0 - self.tokens) / self.rate;
+            wait = self.jittered(Duration::from_secs_f64(missing));
+            self.refill(now + wait);
+        }
+        self.tokens = (self.tokens - 1.0).max(0.0);
+        wait
+    }
+
+    /// Accounts for a request sent to `target`
+    pub fn sent(&mut self, target: IpAddr, replies: &Replies) {
+        self.sent_at(target, replies, Instant::now());
+    }
+
+    fn sent_at(&mut self, target: IpAddr, replies: &Replies, now: Instant) {
+        if !self.adaptive {
+            return;
+        }
+        if replies.known(&target) {
+            self.outstanding.push_back((target, now));
+        }
+        while let Some(&(ip, at)) = self.outstanding.front() {
+            if now.saturating_duration_since(at) < REPLY_GRACE {
+                break;
+            }
+            self.outstanding.pop_front();
+            self.window_sent += 1;
+            if replies.answered_since(&ip, at) {
+                self.window_answered += 1;
+            }
+        }
+        if self.window_sent < ADAPTIVE_WINDOW {
+            return;
+        }
+        // Hosts that used to answer going quiet is what an overloaded network looks like
+        let rate = if self.window_answered * 2 < self.window_sent {
+            (self.rate / 2.0).max(self.min_rate)
+        } else {
+            (self.rate * 2.0).min(self.max_rate)
+        };
+        if rate != self.rate {
+            debug!("Scan rate {:.2}/s -> {:.2}/s", self.rate, rate);
+            self.rate = rate;
+        }
+        self.window_sent = 0;
+        self.window_answered = 0;
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// `duration` scaled by a random factor within 1 ± jitter
+    pub fn jittered(&mut self, duration: Duration) -> Duration {
+        if self.jitter == 0.0 {
+            return duration;
+        }
+        // Xorshift64, plenty for spreading packets
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let unit = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        duration.mul_f64(1.0 + self.jitter * (unit * 2.0 - 1.0))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANNER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+
+    fn pacer(rate: f64, burst: u32, jitter: f64) -> Pacer {
+        Pacer::new(rate, burst, jitter, false, rate).unwrap()
+    }
+
+    fn host(i: u32) -> IpAddr {
+        IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + i))
+    }
+
+    #[test]
+    fn invalid_rates_rejected() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(Pacer::new(rate, 1, 0.0, false, 1.0).is_err(), "{}", rate);
+            assert!(Pacer::new(10.0, 1, 0.0, false, rate).is_err(), "{}", rate);
+        }
+        assert!(Pacer::new(10.0, 1, 0.0, false, 1.0).is_ok());
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        let mut pacer = pacer(10.0, 3, 0.0);
+        let start = pacer.refilled;
+        // The bucket starts full
+        for _ in 0..3 {
+            assert_eq!(pacer.reserve(start), Duration::ZERO);
+        }
+        // Then one token every 100ms
+        assert_eq!(pacer.reserve(start), Duration::from_millis(100));
+        let next = start + Duration::from_millis(100);
+        assert_eq!(pacer.reserve(next), Duration::from_millis(100));
+        // Half refilled
+        let wait = pacer.reserve(next + Duration::from_millis(150));
+        assert_eq!(wait, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn refill_capped_at_burst() {
+        let mut pacer = pacer(10.0, 2, 0.0);
+        let start = pacer.refilled;
+        pacer.reserve(start);
+        pacer.reserve(start);
+        // Idle for long enough to refill many times over, only `burst` requests go at once
+        let later = start + Duration::from_secs(60);
+        assert_eq!(pacer.reserve(later), Duration::ZERO);
+        assert_eq!(pacer.reserve(later), Duration::ZERO);
+        assert_eq!(pacer.reserve(later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let base = Duration::from_secs(10);
+        assert_eq!(pacer(10.0, 1, 0.0).jittered(base), base);
+        let mut pacer = pacer(10.0, 1, 0.2);
+        let waits: Vec<Duration> = (0..1000).map(|_| pacer.jittered(base)).collect();
+        assert!(waits
+            .iter()
+            .all(|wait| (Duration::from_secs(8)..=Duration::from_secs(12)).contains(wait)));
+        // Spread on both sides
+        assert!(waits.iter().any(|wait| *wait < Duration::from_secs(9)));
+        assert!(waits.iter().any(|wait| *wait > Duration::from_secs(11)));
+        // Out of range jitter is clamped
+        let mut pacer = Pacer::new(10.0, 1, 5.0, false, 10.0).unwrap();
+        assert!((0..100).all(|_| pacer.jittered(base) <= base * 2));
+    }
+
+    /// Sends a window of requests to known hosts at `start`, those below `answering`
+    /// reply, then accounts them once their grace delay is over
+    fn window(pacer: &mut Pacer, replies: &Replies, start: Instant, answering: u32) -> Instant {
+        for i in 0..ADAPTIVE_WINDOW as u32 {
+            if i < answering {
+                replies.record(host(i), start + Duration::from_millis(10));
+            }
+            pacer.sent_at(host(i), replies, start);
+        }
+        // Any request past the grace delay flushes the window
+        let end = start + REPLY_GRACE;
+        pacer.sent_at(host(1000), replies, end);
+        end
+    }
+
+    #[test]
+    fn adaptive_halving_and_doubling() {
+        let replies = Replies::new(SCANNER);
+        let mut pacer = Pacer::new(40.0, 1, 0.0, true, 5.0).unwrap();
+        let mut now = pacer.refilled;
+        for i in 0..ADAPTIVE_WINDOW as u32 {
+            replies.record(host(i), now);
+        }
+        now += Duration::from_secs(1);
+        // Most known hosts quiet
+        now = window(&mut pacer, &replies, now, 5);
+        assert_eq!(pacer.rate(), 20.0);
+        now = window(&mut pacer, &replies, now, 5);
+        assert_eq!(pacer.rate(), 10.0);
+        now = window(&mut pacer, &replies, now, 0);
+        assert_eq!(pacer.rate(), 5.0);
+        // Not below the minimum
+        now = window(&mut pacer, &replies, now, 0);
+        assert_eq!(pacer.rate(), 5.0);
+        // Answering again
+        now = window(&mut pacer, &replies, now, ADAPTIVE_WINDOW as u32);
+        assert_eq!(pacer.rate(), 10.0);
+        now = window(&mut pacer, &replies, now, 10);
+        assert_eq!(pacer.rate(), 20.0);
+        now = window(&mut pacer, &replies, now, 15);
+        assert_eq!(pacer.rate(), 40.0);
+        // Not above the configured rate
+        window(&mut pacer, &replies, now, 20);
+        assert_eq!(pacer.rate(), 40.0);
+    }
+
+    #[test]
+    fn unknown_hosts_not_accounted() {
+        let replies = Replies::new(SCANNER);
+        let mut pacer = Pacer::new(40.0, 1, 0.0, true, 5.0).unwrap();
+        let start = pacer.refilled;
+        // Sweeping unused addresses, nobody ever answers
+        for i in 0..ADAPTIVE_WINDOW as u32 * 5 {
+            pacer.sent_at(host(i), &replies, start + REPLY_GRACE * i);
+        }
+        assert_eq!(pacer.rate(), 40.0);
+    }
+
+    #[test]
+    fn replies_to_others_ignored() {
+        let replies = Replies::new(SCANNER);
+        let other = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+        replies.observe(&ArpPacket::reply(other, host(1), SCANNER, host(0)));
+        replies.observe(&ArpPacket::reply(other, host(2), other, host(0)));
+        replies.observe(&ArpPacket::request(other, host(3), host(0)));
+        assert!(replies.known(&host(1)));
+        assert!(!replies.known(&host(2)));
+        assert!(!replies.known(&host(3)));
+    }
+}
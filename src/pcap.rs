@@ -0,0 +1,156 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{sniff::parse_frame, structs::arp::CapturedPacket, App};
+
+/**
+Reader for classic libpcap capture files (tcpdump -w), ethernet link type only.
+
+Global header is 24 bytes long:
+0 -> Magic number, gives endianness and timestamp resolution
+4 -> Version
+8 -> Timezone and accuracy, unused
+16 -> Snapshot length
+20 -> Link type (1 = ethernet)
+
+Then every record starts with a 16 bytes header:
+0 -> Timestamp seconds
+4 -> Timestamp micro or nano seconds
+8 -> Captured length
+12 -> Original length
+*/
+const LINKTYPE_ETHERNET: u32 = 1;
+// Largest snapshot length tcpdump and wireshark write
+const MAX_SNAPLEN: u32 = 262144;
+
+/// Captured frame along with its capture timestamp
+pub type Record = (Duration, Vec<u8>);
+
+#[derive(Debug)]
+pub struct PcapError {
+    pub cause: String,
+}
+
+impl Display for PcapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid capture file: {}", self.cause)
+    }
+}
+
+impl Error for PcapError {}
+
+pub struct PcapReader<R: Read> {
+    input: R,
+    big_endian: bool,
+    nanos: bool,
+    // No frame of the file is longer
+    snaplen: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Box<dyn Error>> {
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+        let (big_endian, nanos) = match header[0..4] {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            _ => {
+                return Err(Box::new(PcapError {
+                    cause: format!("unknown magic number {:x?}", &header[0..4]),
+                }))
+            }
+        };
+        let mut reader = PcapReader {
+            input,
+            big_endian,
+            nanos,
+            snaplen: MAX_SNAPLEN,
+        };
+        // Some writers leave it to 0
+        let snaplen = reader.u32(&header[16..20]);
+        if snaplen != 0 {
+            reader.snaplen = snaplen.min(MAX_SNAPLEN);
+        }
+        let link_type = reader.u32(&header[20..24]);
+        if link_type != LINKTYPE_ETHERNET {
+            return Err(Box::new(PcapError {
+                cause: format!("unsupported link type {}", link_type),
+            }));
+        }
+        Ok(reader)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Next frame of the file, None at the end of the file
+    pub fn next_frame(&mut self) -> Result<Option<Record>, Box<dyn Error>> {
+        let mut header = [0u8; 16];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
+        }
+        let secs = self.u32(&header[0..4]) as u64;
+        let fraction = self.u32(&header[4..8]);
+        let timestamp = if self.nanos {
+            Duration::new(secs, fraction)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(fraction as u64)
+        };
+
+        // Checked before allocating, a corrupted length could ask for gigabytes
+        let caplen = self.u32(&header[8..12]);
+        if caplen > self.snaplen {
+            return Err(Box::new(PcapError {
+                cause: format!(
+                    "frame of {} bytes, longer than the snapshot length {}",
+                    caplen, self.snaplen
+                ),
+            }));
+        }
+        let mut frame = vec![0u8; caplen as usize];
+        self.input.read_exact(&mut frame)?;
+        Ok(Some((timestamp, frame)))
+    }
+}
+
+/// Runs every ARP frame of a capture file through the detection pipeline,
+/// frames keep the timing they were captured with.
+/// Returns the number of ARP frames replayed.
+pub fn replay(path: &Path, iface: &str, app: &mut App) -> Result<usize, Box<dyn Error>> {
+    let mut reader = PcapReader::open(path)?;
+    let start = Instant::now();
+    let mut first = None;
+    let mut count = 0;
+    while let Some((timestamp, frame)) = reader.next_frame()? {
+        let first = *first.get_or_insert(timestamp);
+        if let Some(packet) = parse_frame(&frame) {
+            let mut capture = CapturedPacket::new(packet, iface, false);
+            capture.at = start + timestamp.saturating_sub(first);
+            app.handle_packet(capture);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,261 @@
+use log::error;
+use pnet_datalink::DataLinkReceiver;
+use std::{
+    error::Error,
+    fmt::Display,
+    io::ErrorKind,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    metrics::PROBES_SENT,
+    pacing::Pacer,
+    sniff::{find_interface, local_mac, open_channel, parse_frame},
+    structs::{
+        arp::{ARPOperation, ArpPacket},
+        net::{Device, IpAddr, IpRange, MacAddr},
+    },
+};
+
+/**
+Address conflict detection as described in RFC 5227.
+
+Every address is probed PROBE_NUM times with an ARP request whose sender ip
+is 0.0.0.0, so that the probe itself can't poison any cache. Any host using
+the address, or probing for it at the same time, is reported as a conflict.
+*/
+const PROBE_NUM: usize = 3;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+// Largest range probed at once, a /24
+pub const MAX_PROBE_ADDRESSES: u64 = 256;
+// Probes sent per second, and back to back
+const PROBE_RATE: f64 = 50.0;
+const PROBE_BURST: u32 = 10;
+
+#[derive(Debug, Clone)]
+pub struct ProbeReport {
+    pub range: IpRange,
+    pub conflicts: Vec<Device>,
+}
+
+impl ProbeReport {
+    pub fn is_free(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+impl Display for ProbeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_free() {
+            return write!(f, "{}: free", self.range);
+        }
+        let conflicts: Vec<String> = self.conflicts.iter().map(|dev| dev.to_string()).collect();
+        write!(f, "{}: in use by {}", self.range, conflicts.join(", "))
+    }
+}
+
+pub fn probe_packet(local_mac: &MacAddr, target: &IpAddr) -> ArpPacket {
+    ArpPacket::probe(*local_mac, *target)
+}
+
+pub fn probe(interface_name: &str, range: &IpRange) -> Result<ProbeReport, Box<dyn Error>> {
+    if range.size() > MAX_PROBE_ADDRESSES {
+        return Err(format!(
+            "Can't probe {}: {} addresses, at most {}",
+            range,
+            range.size(),
+            MAX_PROBE_ADDRESSES
+        )
+        .into());
+    }
+    let interface = find_interface(interface_name)?;
+    let config = pnet_datalink::Config {
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = open_channel(&interface, config)?;
+    let local_mac = local_mac(&interface)?;
+    let mut pacer = Pacer::new(PROBE_RATE, PROBE_BURST, 0.0, false, PROBE_RATE)?;
+    let mut conflicts = vec![];
+
+    for round in 0..PROBE_NUM {
+        for target in range.iter() {
+            pacer.acquire();
+            let packet = probe_packet(&local_mac, &target);
+            let raw = packet.raw_padded(&local_mac, &MacAddr::BROADCAST)?;
+            if let Some(Err(e)) = tx.send_to(raw.as_slice(), None) {
+                return Err(Box::new(e));
+            }
+            PROBES_SENT.fetch_add(1, Ordering::Relaxed);
+        }
+        let wait = if round + 1 == PROBE_NUM {
+            ANNOUNCE_WAIT
+        } else {
+            PROBE_INTERVAL
+        };
+        collect_conflicts(
+            &mut *rx,
+            interface_name,
+            &local_mac,
+            range,
+            wait,
+            &mut conflicts,
+        );
+    }
+
+    Ok(ProbeReport {
+        range: range.clone(),
+        conflicts,
+    })
+}
+
+fn collect_conflicts(
+    rx: &mut dyn DataLinkReceiver,
+    iface: &str,
+    local_mac: &MacAddr,
+    range: &IpRange,
+    wait: Duration,
+    conflicts: &mut Vec<Device>,
+) {
+    let deadline = Instant::now() + wait;
+    while Instant::now() < deadline {
+        let packet = match rx.next() {
+            Ok(frame) => match parse_frame(frame) {
+                Some(packet) => packet,
+                None => continue,
+            },
+            Err(e) => {
+                if e.kind() != ErrorKind::TimedOut {
+                    error!("Error occurred while catching packets {}", e);
+                }
+                continue;
+            }
+        };
+        let Some(claimed) = claimed(&packet, local_mac, range) else {
+            continue;
+        };
+        let dev = Device {
+            mac: packet.sender_mac,
+            ip: claimed,
+            iface: iface.to_owned(),
+        };
+        if !conflicts.contains(&dev) {
+            conflicts.push(dev);
+        }
+    }
+}
+
+/// Address of the range another host uses or probes for, as told by the packet
+fn claimed(packet: &ArpPacket, local_mac: &MacAddr, range: &IpRange) -> Option<IpAddr> {
+    if packet.sender_mac == *local_mac {
+        return None;
+    }
+    if range.contains(&packet.sender_ip) {
+        Some(packet.sender_ip)
+    } else if packet.sender_ip.is_unspecified()
+        && matches!(packet.operation, ARPOperation::Request)
+        && range.contains(&packet.target_ip)
+    {
+        // Someone else is probing for the same address
+        Some(packet.target_ip)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::VecDeque, io};
+
+    const LOCAL: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const OTHER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn range() -> IpRange {
+        "192.168.1.10-192.168.1.20".parse().unwrap()
+    }
+
+    #[test]
+    fn conflicts() {
+        let range = range();
+        // In use: any packet sent from an address of the range
+        let reply = ArpPacket::reply(OTHER, ip("192.168.1.10"), LOCAL, IpAddr::UNSPECIFIED);
+        assert_eq!(claimed(&reply, &LOCAL, &range), Some(ip("192.168.1.10")));
+        let request = ArpPacket::request(OTHER, ip("192.168.1.20"), ip("192.168.1.1"));
+        assert_eq!(claimed(&request, &LOCAL, &range), Some(ip("192.168.1.20")));
+        // Probed for by another host at the same time
+        let probe = ArpPacket::probe(OTHER, ip("192.168.1.15"));
+        assert_eq!(claimed(&probe, &LOCAL, &range), Some(ip("192.168.1.15")));
+
+        // Our own probes
+        assert_eq!(
+            claimed(&ArpPacket::probe(LOCAL, ip("192.168.1.15")), &LOCAL, &range),
+            None
+        );
+        // Probes and requests for addresses outside the range
+        assert_eq!(
+            claimed(&ArpPacket::probe(OTHER, ip("192.168.1.30")), &LOCAL, &range),
+            None
+        );
+        let request = ArpPacket::request(OTHER, ip("192.168.1.1"), ip("192.168.1.15"));
+        assert_eq!(claimed(&request, &LOCAL, &range), None);
+    }
+
+    struct Frames(VecDeque<Vec<u8>>, Vec<u8>);
+
+    impl DataLinkReceiver for Frames {
+        fn next(&mut self) -> io::Result<&[u8]> {
+            match self.0.pop_front() {
+                Some(frame) => {
+                    self.1 = frame;
+                    Ok(&self.1)
+                }
+                None => Err(io::Error::new(ErrorKind::TimedOut, "timed out")),
+            }
+        }
+    }
+
+    #[test]
+    fn conflicts_collected_once() {
+        let frame =
+            |packet: ArpPacket| packet.raw(&packet.sender_mac, &MacAddr::BROADCAST).unwrap();
+        let mut rx = Frames(
+            VecDeque::from([
+                frame(ArpPacket::probe(LOCAL, ip("192.168.1.12"))),
+                frame(ArpPacket::gratuitous(OTHER, ip("192.168.1.12"))),
+                frame(ArpPacket::gratuitous(OTHER, ip("192.168.1.12"))),
+                vec![0; 10],
+                frame(ArpPacket::probe(OTHER, ip("192.168.1.13"))),
+            ]),
+            vec![],
+        );
+        let mut conflicts = vec![];
+        let wait = Duration::from_millis(20);
+        collect_conflicts(&mut rx, "eth0", &LOCAL, &range(), wait, &mut conflicts);
+        let claimed: Vec<(MacAddr, IpAddr)> =
+            conflicts.iter().map(|dev| (dev.mac, dev.ip)).collect();
+        assert_eq!(
+            claimed,
+            [(OTHER, ip("192.168.1.12")), (OTHER, ip("192.168.1.13"))]
+        );
+    }
+
+    #[test]
+    fn range_capped() {
+        let err = probe("eth0", &"10.0.0.1-10.0.1.1".parse().unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Can't probe 10.0.0.1-10.0.1.1: 257 addresses, at most 256"
+        );
+        // 256 addresses get past the cap, on to the interface
+        let range = "10.0.0.1-10.0.1.0".parse().unwrap();
+        let err = probe("no-such-iface0", &range).unwrap_err();
+        assert!(!err.to_string().contains("at most"), "{}", err);
+    }
+}
@@ -0,0 +1,59 @@
+use std::{error::Error, fmt::Display, io::Write, str::FromStr};
+
+use crate::structs::net::Device;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format {}, expected text, json or csv", s)),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn write_devices(
+    out: &mut dyn Write,
+    devices: &[Device],
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Text => {
+            for dev in devices {
+                writeln!(out, "{}", dev)?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, devices)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "mac,ip,iface")?;
+            for dev in devices {
+                writeln!(out, "{},{},{}", dev.mac, dev.ip, dev.iface)?;
+            }
+        }
+    }
+    Ok(())
+}
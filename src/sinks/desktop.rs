@@ -0,0 +1,221 @@
+use log::{error, warn};
+use notify_rust::{Notification, Urgency};
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::Sink;
+use crate::structs::{
+    event::{Event, EventKind},
+    net::{Device, DeviceKey},
+};
+
+pub const DEFAULT_MIN_INTERVAL: u64 = 60;
+// Notifications waiting for a slow notification server
+const QUEUE: usize = 20;
+
+/**
+Shows events as desktop notifications through the freedesktop D-Bus
+interface, or the notify-send command when no notification server answers.
+
+The same event for the same device is shown at most once per interval, the
+notifications skipped meanwhile are counted in the next one, or in a summary
+once the device stayed quiet for the interval. They are shown
+one at a time from a background thread, and dropped when too many wait.
+*/
+pub struct DesktopSink {
+    min_interval: Duration,
+    // Last notification and skipped events, by event name and device
+    shown: HashMap<(&'static str, DeviceKey), (Instant, usize)>,
+    tx: SyncSender<(String, Urgency)>,
+}
+
+impl DesktopSink {
+    pub fn new(min_interval: Duration) -> DesktopSink {
+        let (tx, rx) = mpsc::sync_channel::<(String, Urgency)>(QUEUE);
+        // Notification servers may take their time to answer, capture must not wait
+        thread::spawn(move || {
+            for (body, urgency) in rx {
+                notify(&body, urgency);
+            }
+        });
+        DesktopSink {
+            min_interval,
+            shown: HashMap::new(),
+            tx,
+        }
+    }
+
+    /// Number of events skipped since the last notification, None to skip this one
+    fn throttle(&mut self, name: &'static str, dev: &Device, now: Instant) -> Option<usize> {
+        match self.shown.get_mut(&(name, dev.key())) {
+            Some((last, skipped)) if now.saturating_duration_since(*last) < self.min_interval => {
+                *skipped += 1;
+                None
+            }
+            Some(entry) => {
+                let skipped = entry.1;
+                *entry = (now, 0);
+                Some(skipped)
+            }
+            None => {
+                self.shown.insert((name, dev.key()), (now, 0));
+                Some(0)
+            }
+        }
+    }
+
+    /// Forgets devices quiet for the interval, returns the events they skipped
+    /// that no later notification will report
+    fn expire(&mut self, now: Instant) -> Vec<String> {
+        let min_interval = self.min_interval;
+        let mut unreported = vec![];
+        self.shown.retain(|(name, (iface, mac)), (last, skipped)| {
+            if now.saturating_duration_since(*last) < min_interval {
+                return true;
+            }
+            if *skipped > 0 {
+                unreported.push(format!(
+                    "{} {} events of {} on {} not shown",
+                    skipped, name, mac, iface
+                ));
+            }
+            false
+        });
+        unreported.sort();
+        unreported
+    }
+
+    fn send(&self, body: String, urgency: Urgency) {
+        match self.tx.try_send((body, urgency)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => warn!("Too many desktop notifications waiting, dropped"),
+            Err(TrySendError::Disconnected(_)) => error!("Desktop notification worker died"),
+        }
+    }
+}
+
+impl Sink for DesktopSink {
+    fn emit(&mut self, event: &Event) {
+        let dev = match event.kind {
+            EventKind::NewDevice(ref dev) | EventKind::Gone(ref dev) => dev,
+            EventKind::Changed { ref device, .. } => device,
+            EventKind::Alert(ref alert) => &alert.device,
+        };
+        let now = Instant::now();
+        let skipped = self.throttle(event.name(), dev, now);
+        for body in self.expire(now) {
+            self.send(body, Urgency::Low);
+        }
+        let Some(skipped) = skipped else {
+            return;
+        };
+
+        let mut body = event.to_string();
+        if skipped > 0 {
+            body = format!("{}\n({} similar events not shown)", body, skipped);
+        }
+        let urgency = match event.kind {
+            EventKind::Alert(_) => Urgency::Critical,
+            EventKind::Changed { .. } => Urgency::Normal,
+            _ => Urgency::Low,
+        };
+        self.send(body, urgency);
+    }
+}
+
+fn notify(body: &str, urgency: Urgency) {
+    let dbus = Notification::new()
+        .appname("arp-watch")
+        .summary("ARP Watch")
+        .body(body)
+        .urgency(urgency)
+        .show();
+    if dbus.is_ok() {
+        return;
+    }
+
+    let urgency = match urgency {
+        Urgency::Critical => "critical",
+        Urgency::Normal => "normal",
+        Urgency::Low => "low",
+    };
+    // Its output would garble the TUI
+    match Command::new("notify-send")
+        .args(["-a", "arp-watch", "-u", urgency, "ARP Watch", body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+    {
+        Ok(output) if output.status.success() => (),
+        Ok(output) => error!(
+            "notify-send failed: {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => error!("Can't show desktop notification: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::device;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn sink() -> DesktopSink {
+        let (tx, _) = mpsc::sync_channel(1);
+        DesktopSink {
+            min_interval: INTERVAL,
+            shown: HashMap::new(),
+            tx,
+        }
+    }
+
+    #[test]
+    fn throttled_within_interval() {
+        let mut sink = sink();
+        let dev = device("02:00:00:00:00:01", "10.0.0.1", "eth0");
+        let start = Instant::now();
+        assert_eq!(sink.throttle("new-device", &dev, start), Some(0));
+        // Another event name is throttled on its own
+        assert_eq!(sink.throttle("gone", &dev, start), Some(0));
+        for secs in 1..4 {
+            let at = start + Duration::from_secs(secs);
+            assert_eq!(sink.throttle("new-device", &dev, at), None);
+        }
+        // The next one counts those skipped
+        assert_eq!(sink.throttle("new-device", &dev, start + INTERVAL), Some(3));
+        assert_eq!(
+            sink.throttle("new-device", &dev, start + INTERVAL * 2),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn quiet_devices_expired() {
+        let mut sink = sink();
+        let dev = device("02:00:00:00:00:01", "10.0.0.1", "eth0");
+        let start = Instant::now();
+        sink.throttle("new-device", &dev, start);
+        sink.throttle("new-device", &dev, start + Duration::from_secs(1));
+        sink.throttle("gone", &dev, start);
+        assert!(sink.expire(start + Duration::from_secs(59)).is_empty());
+        assert_eq!(sink.shown.len(), 2);
+
+        // Skipped events are reported once, with the entry
+        let unreported = sink.expire(start + INTERVAL);
+        assert_eq!(
+            unreported,
+            ["1 new-device events of 02:00:00:00:00:01 on eth0 not shown"]
+        );
+        assert!(sink.shown.is_empty());
+        assert!(sink.expire(start + INTERVAL * 2).is_empty());
+    }
+}
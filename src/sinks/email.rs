@@ -0,0 +1,386 @@
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+    Transport,
+};
+use log::{error, warn};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    ffi::CStr,
+    mem,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::Sink;
+use crate::{
+    oui::Vendors,
+    structs::{
+        event::{Event, EventKind},
+        net::{Device, IpAddr},
+    },
+};
+
+const QUEUE: usize = 1000;
+const TIMEOUT: Duration = Duration::from_secs(30);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    // Plain connection upgraded with STARTTLS, port 587 by default
+    #[default]
+    Starttls,
+    // TLS from the start, port 465 by default
+    Tls,
+    // Plain text, port 25 by default, for local relays only
+    None,
+}
+
+/// Server side settings of the email sink
+#[derive(Debug, Clone)]
+pub struct SmtpServer {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/**
+Mails events the way arpwatch does: one message per event whose subject
+says what happened ("flip flop", "changed ethernet address"...) and whose
+body lists hostname, ip, macs, vendors and timestamps.
+
+With a digest interval, events are gathered for that long and sent as one
+message instead.
+*/
+pub struct EmailSink {
+    tx: Option<SyncSender<Event>>,
+    // Disconnected when the worker is done
+    done: Receiver<()>,
+}
+
+struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    vendors: Vendors,
+}
+
+impl EmailSink {
+    pub fn new(
+        server: &SmtpServer,
+        from: &str,
+        to: &[String],
+        digest: Duration,
+    ) -> Result<EmailSink, Box<dyn Error>> {
+        if to.is_empty() {
+            return Err("The email sink needs at least one recipient".into());
+        }
+        let mut builder = match server.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&server.host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&server.host)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&server.host),
+        };
+        if let Some(port) = server.port {
+            builder = builder.port(port);
+        }
+        // Validated to come together
+        if let (Some(username), Some(password)) = (&server.username, &server.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let mailer = Mailer {
+            transport: builder.timeout(Some(TIMEOUT)).build(),
+            from: from.parse()?,
+            to: to
+                .iter()
+                .map(|to| to.parse())
+                .collect::<Result<Vec<Mailbox>, _>>()?,
+            vendors: Vendors::load(),
+        };
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        let (done_tx, done) = mpsc::channel();
+        thread::spawn(move || {
+            mailer.run(rx, digest);
+            drop(done_tx);
+        });
+        Ok(EmailSink { tx: Some(tx), done })
+    }
+}
+
+impl Sink for EmailSink {
+    fn emit(&mut self, event: &Event) {
+        let Some(ref tx) = self.tx else {
+            return;
+        };
+        match tx.try_send(event.clone()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => warn!("Email queue full, event dropped"),
+            Err(TrySendError::Disconnected(_)) => error!("Email worker died"),
+        }
+    }
+
+    fn flush(&mut self) {
+        // Closing the queue makes the worker send what it holds and stop
+        if self.tx.take().is_some() {
+            let _ = self.done.recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+impl Mailer {
+    fn run(&self, rx: Receiver<Event>, digest: Duration) {
+        if digest.is_zero() {
+            for event in rx {
+                self.send(&subject(&event), &body(&event, &self.vendors, hostname));
+            }
+            return;
+        }
+
+        let mut batch = vec![];
+        let mut deadline = None;
+        loop {
+            let wait = deadline
+                .map(|deadline: Instant| deadline.saturating_duration_since(Instant::now()));
+            let received = match wait {
+                Some(wait) => rx.recv_timeout(wait),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(event) => {
+                    deadline.get_or_insert_with(|| Instant::now() + digest);
+                    batch.push(event);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.send_digest(mem::take(&mut batch));
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send_digest(batch);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send_digest(&self, batch: Vec<Event>) {
+        if batch.is_empty() {
+            return;
+        }
+        let (subject, body) = digest(&batch, &self.vendors, hostname);
+        self.send(&subject, &body);
+    }
+
+    fn send(&self, subject: &str, body: &str) {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for to in self.to.iter() {
+            message = message.to(to.clone());
+        }
+        let res = message
+            .body(body.to_owned())
+            .map_err(|e| e.to_string())
+            .and_then(|message| self.transport.send(&message).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            error!("Can't send email \"{}\": {}", subject, e);
+        }
+    }
+}
+
+/// Aligned "name: value" lines, as arpwatch writes them, `hostname` resolves the ips
+fn body(event: &Event, vendors: &Vendors, hostname: fn(&IpAddr) -> String) -> String {
+    let mut lines = vec![];
+    let (device, previous, previous_seen) = match event.kind {
+        EventKind::NewDevice(ref dev) | EventKind::Gone(ref dev) => (dev, None, None),
+        EventKind::Changed {
+            ref device,
+            ref previous,
+        } => (device, Some(previous), None),
+        EventKind::Alert(ref alert) => {
+            (&alert.device, alert.previous.as_ref(), alert.previous_seen)
+        }
+    };
+    lines.push(("hostname", hostname(&device.ip)));
+    lines.push(("ip address", device.ip.to_string()));
+    lines.push(("interface", device.iface.clone()));
+    lines.push(("ethernet address", device.mac.to_string()));
+    lines.push(("ethernet vendor", vendor(vendors, device)));
+    if let Some(previous) = previous {
+        if previous.ip != device.ip {
+            lines.push(("old ip address", previous.ip.to_string()));
+        }
+        if previous.mac != device.mac {
+            lines.push(("old ethernet address", previous.mac.to_string()));
+            lines.push(("old ethernet vendor", vendor(vendors, previous)));
+        }
+    }
+    lines.push(("timestamp", timestamp(event.time)));
+    if let Some(previous_seen) = previous_seen {
+        lines.push(("previous timestamp", timestamp(previous_seen)));
+        if let Ok(delta) = event.time.duration_since(previous_seen) {
+            let delta = Duration::from_secs(delta.as_secs());
+            lines.push(("delta", humantime::format_duration(delta).to_string()));
+        }
+    }
+    lines
+        .iter()
+        .map(|(name, value)| format!("{:>20}: {}", name, value))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Subject and body of a single mail for the events, each under its own subject
+fn digest(batch: &[Event], vendors: &Vendors, hostname: fn(&IpAddr) -> String) -> (String, String) {
+    let body = batch
+        .iter()
+        .map(|event| format!("{}\n\n{}", subject(event), body(event, vendors, hostname)))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    (format!("arp-watch digest: {} events", batch.len()), body)
+}
+
+fn vendor(vendors: &Vendors, dev: &Device) -> String {
+    vendors.get(&dev.mac).unwrap_or("<unknown>").to_owned()
+}
+
+/// Subject of the arpwatch message for the event
+fn subject(event: &Event) -> String {
+    let (what, dev) = match event.kind {
+        EventKind::NewDevice(ref dev) => ("new station".to_owned(), dev),
+        EventKind::Changed { ref device, .. } => ("changed ip address".to_owned(), device),
+        EventKind::Alert(ref alert) => (alert.kind.to_string().to_lowercase(), &alert.device),
+        EventKind::Gone(ref dev) => ("station gone".to_owned(), dev),
+    };
+    format!("{} ({}) {}", what, dev.ip, dev.iface)
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Reverse dns name of the ip, "<unknown>" when it has none
+fn hostname(ip: &IpAddr) -> String {
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr {
+            s_addr: u32::from_ne_bytes(ip.octets()),
+        },
+        sin_zero: [0; 8],
+    };
+    let mut host = [0 as libc::c_char; 1025];
+    // SAFETY: addr is a valid sockaddr_in of the given length, host is writable for its length
+    let res = unsafe {
+        libc::getnameinfo(
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if res != 0 {
+        return "<unknown>".to_owned();
+    }
+    // SAFETY: getnameinfo succeeded, host holds a nul terminated string
+    unsafe { CStr::from_ptr(host.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::alert::{Alert, AlertKind},
+        testing::{device, event, TIME},
+    };
+
+    fn resolve(ip: &IpAddr) -> String {
+        format!("host-{}", ip.octets()[3])
+    }
+
+    fn vendors() -> Vendors {
+        Vendors::parse("AABBCC Acme\n112233 Initech\n")
+    }
+
+    fn flip_flop() -> Event {
+        let mut alert = Alert::new(
+            AlertKind::FlipFlop,
+            device("aa:bb:cc:00:00:01", "192.168.1.1", "eth0"),
+            Some(device("11:22:33:00:00:02", "192.168.1.1", "eth0")),
+        );
+        alert.previous_seen = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(TIME - 3725));
+        event(EventKind::Alert(alert))
+    }
+
+    #[test]
+    fn flip_flop_message() {
+        let event = flip_flop();
+        assert_eq!(subject(&event), "flip flop (192.168.1.1) eth0");
+        assert_eq!(
+            body(&event, &vendors(), resolve),
+            [
+                "            hostname: host-1",
+                "          ip address: 192.168.1.1",
+                "           interface: eth0",
+                "    ethernet address: aa:bb:cc:00:00:01",
+                "     ethernet vendor: Acme",
+                "old ethernet address: 11:22:33:00:00:02",
+                " old ethernet vendor: Initech",
+                "           timestamp: 2022-08-01T10:00:00Z",
+                "  previous timestamp: 2022-08-01T08:57:55Z",
+                "               delta: 1h 2m 5s",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn changed_ip_message() {
+        let event = event(EventKind::Changed {
+            previous: device("de:ad:be:ef:00:01", "192.168.1.7", "eth0"),
+            device: device("de:ad:be:ef:00:01", "192.168.1.8", "eth0"),
+        });
+        assert_eq!(subject(&event), "changed ip address (192.168.1.8) eth0");
+        assert_eq!(
+            body(&event, &vendors(), resolve),
+            [
+                "            hostname: host-8",
+                "          ip address: 192.168.1.8",
+                "           interface: eth0",
+                "    ethernet address: de:ad:be:ef:00:01",
+                "     ethernet vendor: <unknown>",
+                "      old ip address: 192.168.1.7",
+                "           timestamp: 2022-08-01T10:00:00Z",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn digest_message() {
+        let new = event(EventKind::NewDevice(device(
+            "aa:bb:cc:00:00:03",
+            "192.168.1.3",
+            "eth0",
+        )));
+        let batch = [new.clone(), flip_flop()];
+        let (subject, body) = digest(&batch, &vendors(), resolve);
+        assert_eq!(subject, "arp-watch digest: 2 events");
+        let sections: Vec<&str> = body.split("\n\n").collect();
+        assert_eq!(
+            sections,
+            [
+                "new station (192.168.1.3) eth0",
+                super::body(&new, &vendors(), resolve).as_str(),
+                "flip flop (192.168.1.1) eth0",
+                super::body(&flip_flop(), &vendors(), resolve).as_str(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use log::{error, warn};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::Sink;
+use crate::structs::{
+    event::{Event, EventKind},
+    net::Device,
+};
+
+pub const DEFAULT_TIMEOUT: u64 = 10;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+const QUEUE: usize = 100;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/**
+Runs a shell command for each event. The event is written as json on its
+stdin and its main fields are set in the environment:
+
+ARPWATCH_EVENT    event name, the alert kind for alerts
+ARPWATCH_CLASS    new-device, changed, alert or gone
+ARPWATCH_TIME     rfc3339 timestamp
+ARPWATCH_IFACE, ARPWATCH_IP, ARPWATCH_MAC
+ARPWATCH_OLD_IP, ARPWATCH_OLD_MAC    when the event has a previous device
+
+Commands still running after the timeout are killed, at most `concurrency`
+run at once and events are dropped when too many are waiting. Their output
+would garble the TUI, stdout is discarded and stderr logged.
+*/
+pub struct HookSink {
+    command: String,
+    tx: Option<SyncSender<Event>>,
+    // Disconnected when every runner is done
+    done: Receiver<()>,
+    timeout: Duration,
+}
+
+impl HookSink {
+    pub fn new(command: &str, timeout: Duration, concurrency: usize) -> HookSink {
+        let (tx, rx) = mpsc::sync_channel::<Event>(QUEUE);
+        let rx = Arc::new(Mutex::new(rx));
+        let (done_tx, done) = mpsc::channel::<()>();
+        for _ in 0..concurrency {
+            let rx = rx.clone();
+            let done_tx = done_tx.clone();
+            let command = command.to_owned();
+            thread::spawn(move || {
+                loop {
+                    // The lock is released as soon as an event is taken
+                    let event = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok(event) = event else {
+                        break;
+                    };
+                    run(&command, &event, timeout);
+                }
+                drop(done_tx);
+            });
+        }
+        HookSink {
+            command: command.to_owned(),
+            tx: Some(tx),
+            done,
+            timeout,
+        }
+    }
+}
+
+impl Sink for HookSink {
+    fn emit(&mut self, event: &Event) {
+        let Some(ref tx) = self.tx else {
+            return;
+        };
+        match tx.try_send(event.clone()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                warn!("Too many events waiting for hook {}, dropped", self.command)
+            }
+            Err(TrySendError::Disconnected(_)) => error!("Hook {} runners died", self.command),
+        }
+    }
+
+    fn flush(&mut self) {
+        // Commands already started get their full timeout
+        if self.tx.take().is_some() {
+            let _ = self.done.recv_timeout(self.timeout);
+        }
+    }
+}
+
+fn run(command: &str, event: &Event, timeout: Duration) {
+    let mut child = match Command::new("sh")
+        .args(["-c", command])
+        .envs(env(event))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // Own process group, so that a timeout kills what the shell started too
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Can't run hook {}: {}", command, e);
+            return;
+        }
+    };
+    if let Some(stderr) = child.stderr.take() {
+        // Read as it comes, a full pipe would block the command until its timeout
+        let command = command.to_owned();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                warn!("Hook {}: {}", command, line);
+            }
+        });
+    }
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command may not read it, a broken pipe is fine
+        let _ = writeln!(stdin, "{}", event.to_json());
+    }
+    wait(command, &mut child, timeout);
+}
+
+fn wait(command: &str, child: &mut Child, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    warn!("Hook {} failed: {}", command, status);
+                }
+                return;
+            }
+            Ok(None) if Instant::now() >= deadline => {
+                warn!("Hook {} timed out after {:?}, killed", command, timeout);
+                // SAFETY: kill has no memory safety requirement
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.wait();
+                return;
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                error!("Can't wait for hook {}: {}", command, e);
+                return;
+            }
+        }
+    }
+}
+
+fn env(event: &Event) -> Vec<(&'static str, String)> {
+    let (class, device, previous): (&str, &Device, Option<&Device>) = match event.kind {
+        EventKind::NewDevice(ref dev) => ("new-device", dev, None),
+        EventKind::Changed {
+            ref device,
+            ref previous,
+        } => ("changed", device, Some(previous)),
+        EventKind::Alert(ref alert) => ("alert", &alert.device, alert.previous.as_ref()),
+        EventKind::Gone(ref dev) => ("gone", dev, None),
+    };
+    let mut vars = vec![
+        ("ARPWATCH_EVENT", event.name().to_owned()),
+        ("ARPWATCH_CLASS", class.to_owned()),
+        (
+            "ARPWATCH_TIME",
+            humantime::format_rfc3339_millis(event.time).to_string(),
+        ),
+        ("ARPWATCH_IFACE", device.iface.clone()),
+        ("ARPWATCH_IP", device.ip.to_string()),
+        ("ARPWATCH_MAC", device.mac.to_string()),
+    ];
+    if let Some(previous) = previous {
+        vars.push(("ARPWATCH_OLD_IP", previous.ip.to_string()));
+        vars.push(("ARPWATCH_OLD_MAC", previous.mac.to_string()));
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::alert::{Alert, AlertKind},
+        testing::{device, event, scratch_dir},
+    };
+    use std::fs;
+
+    #[test]
+    fn environment() {
+        let alert = Alert::new(
+            AlertKind::FlipFlop,
+            device("aa:bb:cc:00:00:01", "192.168.1.1", "eth0"),
+            Some(device("11:22:33:00:00:02", "192.168.1.1", "eth0")),
+        );
+        assert_eq!(
+            env(&event(EventKind::Alert(alert))),
+            [
+                ("ARPWATCH_EVENT", "flip-flop".to_owned()),
+                ("ARPWATCH_CLASS", "alert".to_owned()),
+                ("ARPWATCH_TIME", "2022-08-01T10:00:00.000Z".to_owned()),
+                ("ARPWATCH_IFACE", "eth0".to_owned()),
+                ("ARPWATCH_IP", "192.168.1.1".to_owned()),
+                ("ARPWATCH_MAC", "aa:bb:cc:00:00:01".to_owned()),
+                ("ARPWATCH_OLD_IP", "192.168.1.1".to_owned()),
+                ("ARPWATCH_OLD_MAC", "11:22:33:00:00:02".to_owned()),
+            ]
+        );
+        let vars = env(&event(EventKind::Gone(device(
+            "aa:bb:cc:00:00:01",
+            "192.168.1.1",
+            "eth0",
+        ))));
+        assert_eq!(vars[1], ("ARPWATCH_CLASS", "gone".to_owned()));
+        assert!(vars
+            .iter()
+            .all(|(name, _)| !name.starts_with("ARPWATCH_OLD_")));
+    }
+
+    #[test]
+    fn killed_after_timeout() {
+        let dir = scratch_dir("hook");
+        let pidfile = dir.join("pid");
+        // The shell waits for a command it started in the background
+        let command = format!("sleep 30 & echo $! > {}; wait", pidfile.display());
+        let dev = device("aa:bb:cc:00:00:01", "192.168.1.1", "eth0");
+        let start = Instant::now();
+        run(
+            &command,
+            &event(EventKind::NewDevice(dev)),
+            Duration::from_millis(300),
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let pid = fs::read_to_string(&pidfile).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // Killed with the whole process group, at most left as a zombie. The signal
+        // may take a moment to get delivered.
+        let dead = || {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+            let state = stat
+                .rsplit(") ")
+                .next()
+                .and_then(|rest| rest.chars().next());
+            matches!(state, None | Some('Z') | Some('X'))
+        };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !dead() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dead());
+    }
+}
@@ -0,0 +1,103 @@
+use log::error;
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::Sink;
+use crate::structs::event::Event;
+
+/// Writes one json object per line and per event
+pub struct JsonSink {
+    out: Box<dyn Write + Send>,
+    // Opened again on reload, stdout when none
+    path: Option<PathBuf>,
+}
+
+impl JsonSink {
+    pub fn stdout() -> JsonSink {
+        JsonSink {
+            out: Box::new(io::stdout()),
+            path: None,
+        }
+    }
+
+    /// Appends to the file, which is opened again on reload so that log rotation
+    /// tools can move it away
+    pub fn file(path: &Path) -> Result<JsonSink, Box<dyn Error>> {
+        Ok(JsonSink {
+            out: Box::new(open(path)?),
+            path: Some(path.to_owned()),
+        })
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Sink for JsonSink {
+    fn emit(&mut self, event: &Event) {
+        if let Err(e) = writeln!(self.out, "{}", event.to_json()).and_then(|_| self.out.flush()) {
+            error!("Can't write event: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    fn reopen(&mut self) {
+        let Some(ref path) = self.path else {
+            return;
+        };
+        let _ = self.out.flush();
+        // The old file is kept on failure, events still go somewhere
+        match open(path) {
+            Ok(file) => self.out = Box::new(file),
+            Err(e) => error!("Can't open {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::event::EventKind,
+        testing::{device, event, scratch_dir},
+    };
+    use std::fs;
+
+    fn new_device(ip: &str) -> Event {
+        event(EventKind::NewDevice(device(
+            "02:00:00:00:00:01",
+            ip,
+            "eth0",
+        )))
+    }
+
+    #[test]
+    fn reopened_after_rotation() {
+        let dir = scratch_dir("json");
+        let path = dir.join("events.json");
+        let rotated = dir.join("events.json.1");
+        let mut sink = JsonSink::file(&path).unwrap();
+        sink.emit(&new_device("10.0.0.1"));
+        fs::rename(&path, &rotated).unwrap();
+        // Still written to the moved file until reopened
+        sink.emit(&new_device("10.0.0.2"));
+        sink.reopen();
+        sink.emit(&new_device("10.0.0.3"));
+
+        let old = fs::read_to_string(&rotated).unwrap();
+        let new = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(old.lines().count(), 2);
+        assert!(old.contains("10.0.0.1") && old.contains("10.0.0.2"));
+        assert_eq!(new.lines().count(), 1);
+        assert!(new.contains("10.0.0.3"));
+    }
+}
@@ -0,0 +1,319 @@
+use log::{info, warn};
+use serde::Deserialize;
+use std::{error::Error, path::PathBuf, time::Duration};
+
+pub mod desktop;
+pub mod email;
+pub mod hook;
+pub mod json;
+pub mod syslog;
+pub mod webhook;
+
+use crate::structs::event::{Event, EventClass, EventKind};
+use desktop::{DesktopSink, DEFAULT_MIN_INTERVAL};
+use email::{EmailSink, SmtpSecurity, SmtpServer};
+use hook::{HookSink, DEFAULT_CONCURRENCY, DEFAULT_TIMEOUT};
+use json::JsonSink;
+use syslog::{SyslogSink, Transport, DEFAULT_FACILITY};
+use webhook::{WebhookFormat, WebhookSink, DEFAULT_QUEUE, DEFAULT_RETRIES};
+
+/// Destination events are sent to, on top of the TUI
+pub trait Sink {
+    fn emit(&mut self, event: &Event);
+
+    /// Called before exiting, sinks buffering events must send them
+    fn flush(&mut self) {}
+
+    /// Called on reload, sinks writing to files must open them again
+    fn reopen(&mut self) {}
+}
+
+/// One `[[sinks]]` entry of the config, `classes` restricts the events it gets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkConfig {
+    Log {
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Json {
+        // Stdout when not given
+        path: Option<PathBuf>,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Syslog {
+        #[serde(default)]
+        transport: Transport,
+        // Socket path for unix, host:port of the collector for udp and tcp
+        address: Option<String>,
+        #[serde(default = "default_facility")]
+        facility: u8,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Desktop {
+        // Seconds between two notifications of the same event for the same device
+        #[serde(default = "default_min_interval")]
+        min_interval: u64,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Webhook {
+        url: String,
+        #[serde(default)]
+        format: WebhookFormat,
+        // Attempts after the first one, the wait doubling each time
+        #[serde(default = "default_retries")]
+        retries: u32,
+        // Events waiting to be sent, newer ones are dropped when full
+        #[serde(default = "default_queue")]
+        queue: usize,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Email {
+        server: String,
+        // Depends on security when not given
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+        // Seconds events are gathered for into a single mail, 0 for a mail per event
+        #[serde(default)]
+        digest: u64,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+    Hook {
+        // Run with sh -c
+        command: String,
+        // Seconds before the command is killed
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        // Commands running at the same time
+        #[serde(default = "default_concurrency")]
+        concurrency: usize,
+        #[serde(default)]
+        classes: Vec<EventClass>,
+    },
+}
+
+fn default_facility() -> u8 {
+    DEFAULT_FACILITY
+}
+
+fn default_min_interval() -> u64 {
+    DEFAULT_MIN_INTERVAL
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+fn default_queue() -> usize {
+    DEFAULT_QUEUE
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT
+}
+
+fn default_concurrency() -> usize {
+    DEFAULT_CONCURRENCY
+}
+
+impl SinkConfig {
+    pub fn classes(&self) -> &[EventClass] {
+        match self {
+            SinkConfig::Log { classes } => classes,
+            SinkConfig::Json { classes, .. } => classes,
+            SinkConfig::Syslog { classes, .. } => classes,
+            SinkConfig::Desktop { classes, .. } => classes,
+            SinkConfig::Webhook { classes, .. } => classes,
+            SinkConfig::Email { classes, .. } => classes,
+            SinkConfig::Hook { classes, .. } => classes,
+        }
+    }
+
+    /// Settings that would otherwise be silently ignored
+    pub fn validate(&self) -> Result<(), String> {
+        if let SinkConfig::Email {
+            username, password, ..
+        } = self
+        {
+            match (username, password) {
+                (Some(_), None) => return Err("email username given without password".to_owned()),
+                (None, Some(_)) => return Err("email password given without username".to_owned()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the sink writes to stdout, where it would garble the terminal UI
+    pub fn writes_stdout(&self) -> bool {
+        matches!(self, SinkConfig::Json { path: None, .. })
+    }
+
+    pub fn build(&self) -> Result<Box<dyn Sink>, Box<dyn Error>> {
+        match self {
+            SinkConfig::Log { .. } => Ok(Box::new(LogSink)),
+            SinkConfig::Json { path, .. } => match path {
+                Some(path) => Ok(Box::new(JsonSink::file(path)?)),
+                None => Ok(Box::new(JsonSink::stdout())),
+            },
+            SinkConfig::Syslog {
+                transport,
+                address,
+                facility,
+                ..
+            } => Ok(Box::new(SyslogSink::new(
+                *transport,
+                address.as_deref(),
+                *facility,
+            )?)),
+            SinkConfig::Desktop { min_interval, .. } => Ok(Box::new(DesktopSink::new(
+                Duration::from_secs(*min_interval),
+            ))),
+            SinkConfig::Webhook {
+                url,
+                format,
+                retries,
+                queue,
+                ..
+            } => {
+                if *queue == 0 {
+                    return Err("Webhook queue must hold at least one event".into());
+                }
+                Ok(Box::new(WebhookSink::new(url, *format, *retries, *queue)))
+            }
+            SinkConfig::Email {
+                server,
+                port,
+                security,
+                username,
+                password,
+                from,
+                to,
+                digest,
+                ..
+            } => {
+                let server = SmtpServer {
+                    host: server.clone(),
+                    port: *port,
+                    security: *security,
+                    username: username.clone(),
+                    password: password.clone(),
+                };
+                Ok(Box::new(EmailSink::new(
+                    &server,
+                    from,
+                    to,
+                    Duration::from_secs(*digest),
+                )?))
+            }
+            SinkConfig::Hook {
+                command,
+                timeout,
+                concurrency,
+                ..
+            } => {
+                if *concurrency == 0 {
+                    return Err("Hook concurrency must be at least 1".into());
+                }
+                Ok(Box::new(HookSink::new(
+                    command,
+                    Duration::from_secs(*timeout),
+                    *concurrency,
+                )))
+            }
+        }
+    }
+}
+
+type FilteredSink = (Vec<EventClass>, Box<dyn Sink>);
+
+#[derive(Default)]
+pub struct Sinks {
+    // Built from the config, replaced on reload
+    configured: Vec<FilteredSink>,
+    // Added by the program itself, kept on reload
+    pinned: Vec<FilteredSink>,
+    // Stdout is used by the terminal UI
+    no_stdout: bool,
+}
+
+impl Sinks {
+    pub fn new(configs: &[SinkConfig]) -> Result<Sinks, Box<dyn Error>> {
+        let mut sinks = Sinks::default();
+        sinks.reconfigure(configs)?;
+        Ok(sinks)
+    }
+
+    /// Sinks next to the terminal UI, those writing to stdout are rejected
+    pub fn without_stdout(configs: &[SinkConfig]) -> Result<Sinks, Box<dyn Error>> {
+        let mut sinks = Sinks {
+            no_stdout: true,
+            ..Sinks::default()
+        };
+        sinks.reconfigure(configs)?;
+        Ok(sinks)
+    }
+
+    /// Replaces the sinks built from the config, pinned ones are kept
+    pub fn reconfigure(&mut self, configs: &[SinkConfig]) -> Result<(), Box<dyn Error>> {
+        if self.no_stdout && configs.iter().any(SinkConfig::writes_stdout) {
+            return Err("A json sink needs a path next to the terminal UI".into());
+        }
+        let mut configured = vec![];
+        for config in configs {
+            configured.push((config.classes().to_vec(), config.build()?));
+        }
+        for (_, sink) in self.configured.iter_mut() {
+            sink.flush();
+        }
+        self.configured = configured;
+        Ok(())
+    }
+
+    /// Opens again the files of the pinned sinks, configured ones are rebuilt on reload
+    pub fn reopen(&mut self) {
+        for (_, sink) in self.pinned.iter_mut() {
+            sink.reopen();
+        }
+    }
+
+    pub fn pin(&mut self, sink: Box<dyn Sink>) {
+        self.pinned.push((vec![], sink));
+    }
+
+    pub fn emit(&mut self, event: &Event) {
+        for (classes, sink) in self.configured.iter_mut().chain(self.pinned.iter_mut()) {
+            if classes.is_empty() || classes.iter().any(|class| event.is(*class)) {
+                sink.emit(event);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for (_, sink) in self.configured.iter_mut().chain(self.pinned.iter_mut()) {
+            sink.flush();
+        }
+    }
+}
+
+/// Writes events to the application log, alerts as warnings
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn emit(&mut self, event: &Event) {
+        match event.kind {
+            EventKind::Alert(_) => warn!("{}", event),
+            _ => info!("{}", event),
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use log::{error, warn};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    os::unix::net::UnixDatagram,
+    process,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use super::Sink;
+use crate::structs::{
+    alert::AlertKind,
+    event::{Event, EventKind},
+    net::{Device, MacAddr},
+};
+
+pub const DEFAULT_SOCKET: &str = "/dev/log";
+// Facility daemon
+pub const DEFAULT_FACILITY: u8 = 3;
+// Structured data id, 32473 is the enterprise number reserved for examples
+const SD_ID: &str = "arpwatch@32473";
+// Messages waiting for a slow or unreachable collector
+const QUEUE: usize = 100;
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+// How long exiting waits for queued messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Unix,
+    Udp,
+    Tcp,
+}
+
+enum Connection {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+/**
+Sends events as RFC 5424 messages, to the local syslog socket or to a
+collector over udp or tcp. Tcp messages are framed with their length
+(RFC 6587 octet counting).
+
+Messages are sent from a background thread through a bounded queue, a
+collector that is down or slow drops them instead of stalling the capture.
+
+<28>1 2022-08-01T10:00:00.000Z host arp-watch 42 gratuitous-conflict [arpwatch@32473 iface="eth0" ip="192.168.1.1" old_mac="aa:bb:cc:dd:ee:ff" new_mac="11:22:33:44:55:66"] Gratuitous ARP conflict: ...
+*/
+pub struct SyslogSink {
+    address: String,
+    facility: u8,
+    hostname: String,
+    // None once flushed
+    tx: Option<SyncSender<String>>,
+    // Disconnected when the worker is done
+    done: Receiver<()>,
+    dropped: usize,
+}
+
+/// Connection to the syslog daemon or collector, owned by the worker
+struct Syslog {
+    transport: Transport,
+    address: String,
+    // None after a send failed, reconnected on the next message
+    conn: Option<Connection>,
+}
+
+impl SyslogSink {
+    pub fn new(
+        transport: Transport,
+        address: Option<&str>,
+        facility: u8,
+    ) -> Result<SyslogSink, Box<dyn Error>> {
+        if facility > 23 {
+            return Err(format!("Invalid syslog facility {}, must be 0 to 23", facility).into());
+        }
+        let address = match (transport, address) {
+            (_, Some(address)) => address.to_owned(),
+            (Transport::Unix, None) => DEFAULT_SOCKET.to_owned(),
+            (_, None) => return Err("A syslog collector needs an address".into()),
+        };
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE);
+        let (done_tx, done) = mpsc::channel();
+        let mut syslog = Syslog {
+            transport,
+            address: address.clone(),
+            conn: None,
+        };
+        thread::spawn(move || {
+            // Reported early, but a collector down at startup may come back later
+            match syslog.connect() {
+                Ok(conn) => syslog.conn = Some(conn),
+                Err(e) => warn!("Can't connect to syslog {}: {}", syslog.address, e),
+            }
+            for msg in rx {
+                // A collector that restarted is worth a second try
+                if let Err(e) = syslog.send(&msg).or_else(|_| syslog.send(&msg)) {
+                    error!("Can't send event to syslog {}: {}", syslog.address, e);
+                }
+            }
+            drop(done_tx);
+        });
+        Ok(SyslogSink {
+            address,
+            facility,
+            hostname: hostname(),
+            tx: Some(tx),
+            done,
+            dropped: 0,
+        })
+    }
+
+    fn format(&self, event: &Event) -> String {
+        message(self.facility, &self.hostname, process::id(), event)
+    }
+}
+
+impl Syslog {
+    fn connect(&self) -> io::Result<Connection> {
+        match self.transport {
+            Transport::Unix => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(&self.address)?;
+                Ok(Connection::Unix(socket))
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(&self.address)?;
+                Ok(Connection::Udp(socket))
+            }
+            Transport::Tcp => {
+                let mut last = None;
+                for addr in self.address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, TCP_TIMEOUT) {
+                        Ok(stream) => {
+                            stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                            return Ok(Connection::Tcp(stream));
+                        }
+                        Err(e) => last = Some(e),
+                    }
+                }
+                Err(last.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address resolves to nothing")
+                }))
+            }
+        }
+    }
+
+    fn send(&mut self, msg: &str) -> io::Result<()> {
+        if self.conn.is_none() {
+            self.conn = Some(self.connect()?);
+        }
+        let res = match self.conn {
+            Some(Connection::Unix(ref socket)) => socket.send(msg.as_bytes()).map(|_| ()),
+            Some(Connection::Udp(ref socket)) => socket.send(msg.as_bytes()).map(|_| ()),
+            Some(Connection::Tcp(ref mut stream)) => stream
+                .write_all(frame(msg).as_bytes())
+                .and_then(|_| stream.flush()),
+            None => Ok(()),
+        };
+        if res.is_err() {
+            self.conn = None;
+        }
+        res
+    }
+}
+
+impl Sink for SyslogSink {
+    fn emit(&mut self, event: &Event) {
+        let Some(ref tx) = self.tx else {
+            return;
+        };
+        match tx.try_send(self.format(event)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                warn!(
+                    "Syslog {} queue full, {} events dropped",
+                    self.address, self.dropped
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => error!("Syslog {} worker died", self.address),
+        }
+    }
+
+    fn flush(&mut self) {
+        // Closing the queue ends the worker once it is empty
+        if self.tx.take().is_some() {
+            let _ = self.done.recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+/// RFC 5424 message of the event, as sent over datagrams
+fn message(facility: u8, hostname: &str, pid: u32, event: &Event) -> String {
+    format!(
+        "<{}>1 {} {} arp-watch {} {} {} {}",
+        facility * 8 + severity(event),
+        humantime::format_rfc3339_millis(event.time),
+        hostname,
+        pid,
+        event.name(),
+        structured_data(event),
+        event
+    )
+}
+
+/// Octet counted message for stream transports: its length in bytes, a space, then the message
+fn frame(msg: &str) -> String {
+    format!("{} {}", msg.len(), msg)
+}
+
+/// Alerts are warning or worse, a host stealing a known binding being the worst
+fn severity(event: &Event) -> u8 {
+    match event.kind {
+        EventKind::Alert(ref alert) => match alert.kind {
+            AlertKind::ProtectedHostChanged => 1,
+            AlertKind::GratuitousConflict
+            | AlertKind::ChangedEthernetAddress
+            | AlertKind::PoisonedKernelCache => 2,
+            AlertKind::UnsolicitedReply | AlertKind::FlipFlop => 3,
+            AlertKind::GratuitousStorm | AlertKind::MacOnMultipleSegments => 4,
+        },
+        EventKind::Changed { .. } => 5,
+        EventKind::NewDevice(_) | EventKind::Gone(_) => 6,
+    }
+}
+
+fn structured_data(event: &Event) -> String {
+    let (device, old_mac, new_mac): (&Device, Option<&MacAddr>, Option<&MacAddr>) = match event.kind
+    {
+        EventKind::NewDevice(ref dev) => (dev, None, Some(&dev.mac)),
+        EventKind::Changed { ref device, .. } => (device, None, Some(&device.mac)),
+        EventKind::Alert(ref alert) => (
+            &alert.device,
+            alert.previous.as_ref().map(|dev| &dev.mac),
+            Some(&alert.device.mac),
+        ),
+        EventKind::Gone(ref dev) => (dev, Some(&dev.mac), None),
+    };
+    let mut params = vec![
+        ("iface", device.iface.clone()),
+        ("ip", device.ip.to_string()),
+    ];
+    if let EventKind::Changed { ref previous, .. } = event.kind {
+        params.push(("old_ip", previous.ip.to_string()));
+    }
+    if let Some(mac) = old_mac {
+        params.push(("old_mac", mac.to_string()));
+    }
+    if let Some(mac) = new_mac {
+        params.push(("new_mac", mac.to_string()));
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    format!("[{} {}]", SD_ID, params.join(" "))
+}
+
+/// Param values must escape '"', '\' and ']'
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_owned())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::alert::Alert,
+        testing::{device, event},
+    };
+
+    #[test]
+    fn alert_message() {
+        let alert = Alert::new(
+            AlertKind::ProtectedHostChanged,
+            device("11:22:33:44:55:66", "192.168.1.1", "eth0"),
+            Some(device("aa:bb:cc:dd:ee:ff", "192.168.1.1", "eth0")),
+        );
+        assert_eq!(
+            message(DEFAULT_FACILITY, "host", 42, &event(EventKind::Alert(alert))),
+            "<25>1 2022-08-01T10:00:00.000Z host arp-watch 42 protected-host-changed \
+             [arpwatch@32473 iface=\"eth0\" ip=\"192.168.1.1\" old_mac=\"aa:bb:cc:dd:ee:ff\" \
+             new_mac=\"11:22:33:44:55:66\"] Protected host changed: \
+             [11:22:33:44:55:66] @ 192.168.1.1 on eth0 (was [aa:bb:cc:dd:ee:ff] @ 192.168.1.1 on eth0)"
+        );
+    }
+
+    #[test]
+    fn changed_and_gone_messages() {
+        let changed = EventKind::Changed {
+            previous: device("aa:bb:cc:dd:ee:ff", "192.168.1.7", "eth0"),
+            device: device("aa:bb:cc:dd:ee:ff", "192.168.1.8", "eth0"),
+        };
+        assert_eq!(
+            message(16, "-", 1, &event(changed)),
+            "<133>1 2022-08-01T10:00:00.000Z - arp-watch 1 changed \
+             [arpwatch@32473 iface=\"eth0\" ip=\"192.168.1.8\" old_ip=\"192.168.1.7\" \
+             new_mac=\"aa:bb:cc:dd:ee:ff\"] Changed: \
+             [aa:bb:cc:dd:ee:ff] @ 192.168.1.7 on eth0 -> [aa:bb:cc:dd:ee:ff] @ 192.168.1.8 on eth0"
+        );
+        let gone = EventKind::Gone(device("aa:bb:cc:dd:ee:ff", "192.168.1.8", "eth0"));
+        assert_eq!(
+            message(DEFAULT_FACILITY, "host", 42, &event(gone)),
+            "<30>1 2022-08-01T10:00:00.000Z host arp-watch 42 gone \
+             [arpwatch@32473 iface=\"eth0\" ip=\"192.168.1.8\" old_mac=\"aa:bb:cc:dd:ee:ff\"] \
+             Gone: [aa:bb:cc:dd:ee:ff] @ 192.168.1.8 on eth0"
+        );
+    }
+
+    #[test]
+    fn param_values_escaped() {
+        assert_eq!(escape(r#"a"b\c]d"#), r#"a\"b\\c\]d"#);
+        assert_eq!(escape("eth0[1]"), r#"eth0[1\]"#);
+        let dev = EventKind::NewDevice(device("aa:bb:cc:dd:ee:ff", "10.0.0.1", r#"br"0]\"#));
+        assert_eq!(
+            structured_data(&event(dev)),
+            r#"[arpwatch@32473 iface="br\"0\]\\" ip="10.0.0.1" new_mac="aa:bb:cc:dd:ee:ff"]"#
+        );
+    }
+
+    #[test]
+    fn octet_counted_frames() {
+        assert_eq!(frame("<30>1 - - - - - -"), "17 <30>1 - - - - - -");
+        // Bytes, not characters
+        assert_eq!(frame("caf\u{e9}"), "5 caf\u{e9}");
+        assert_eq!(frame(""), "0 ");
+    }
+}
@@ -0,0 +1,218 @@
+use log::{error, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+use ureq::Agent;
+
+use super::Sink;
+use crate::structs::event::Event;
+
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_QUEUE: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// How long exiting waits for queued events
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    // The event json, as written by the json sink
+    #[default]
+    Raw,
+    Slack,
+    Mattermost,
+}
+
+impl WebhookFormat {
+    fn body(&self, event: &Event) -> String {
+        match self {
+            WebhookFormat::Raw => event.to_json(),
+            WebhookFormat::Slack => json!({ "text": format!("*ARP Watch* {}", event) }).to_string(),
+            WebhookFormat::Mattermost => json!({
+                "username": "arp-watch",
+                "text": format!("**ARP Watch** {}", event),
+            })
+            .to_string(),
+        }
+    }
+}
+
+/**
+POSTs events to an http endpoint from a background thread.
+
+Events wait in a bounded queue while the endpoint is slow or down, once it
+is full new events are dropped instead of stalling the capture.
+*/
+pub struct WebhookSink {
+    url: String,
+    format: WebhookFormat,
+    // None once flushed
+    tx: Option<SyncSender<String>>,
+    // Disconnected when the worker is done
+    done: Receiver<()>,
+    dropped: usize,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str, format: WebhookFormat, retries: u32, queue: usize) -> WebhookSink {
+        let (tx, rx) = mpsc::sync_channel::<String>(queue);
+        let (done_tx, done) = mpsc::channel();
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let worker_url = url.to_owned();
+        thread::spawn(move || {
+            for body in rx {
+                post(&agent, &worker_url, &body, retries);
+            }
+            drop(done_tx);
+        });
+        WebhookSink {
+            url: url.to_owned(),
+            format,
+            tx: Some(tx),
+            done,
+            dropped: 0,
+        }
+    }
+}
+
+impl Sink for WebhookSink {
+    fn emit(&mut self, event: &Event) {
+        let tx = match self.tx {
+            Some(ref tx) => tx,
+            None => return,
+        };
+        match tx.try_send(self.format.body(event)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                warn!(
+                    "Webhook {} queue full, {} events dropped",
+                    self.url, self.dropped
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => error!("Webhook {} worker died", self.url),
+        }
+    }
+
+    fn flush(&mut self) {
+        // Closing the queue ends the worker once it is empty
+        if self.tx.take().is_some() {
+            let _ = self.done.recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+/// Retries server errors and network failures, doubling the wait each time
+fn post(agent: &Agent, url: &str, body: &str, retries: u32) {
+    for attempt in 0..=retries {
+        let res = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(body);
+        let e = match res {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        let status = match e {
+            ureq::Error::Status(code, _) => Some(code),
+            ureq::Error::Transport(_) => None,
+        };
+        if !retryable(status) {
+            error!("Webhook {} refused the event: {}", url, e);
+            return;
+        }
+        if attempt == retries {
+            error!("Webhook {} failed, event dropped: {}", url, e);
+            return;
+        }
+        let backoff = backoff(attempt);
+        warn!("Webhook {} failed, retrying in {:?}: {}", url, backoff, e);
+        thread::sleep(backoff);
+    }
+}
+
+/// Whether a failed post is worth another try: network failures (no status),
+/// rate limiting and server errors. Other statuses would fail the same way again.
+fn retryable(status: Option<u16>) -> bool {
+    match status {
+        None => true,
+        Some(code) => code == 429 || code >= 500,
+    }
+}
+
+/// Wait before the retry following the failed `attempt`, counted from 0
+fn backoff(attempt: u32) -> Duration {
+    FIRST_BACKOFF
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        structs::event::EventKind,
+        testing::{device, event},
+    };
+
+    #[test]
+    fn retry_decision() {
+        for (status, retry) in [
+            (None, true),
+            (Some(429), true),
+            (Some(500), true),
+            (Some(503), true),
+            (Some(400), false),
+            (Some(401), false),
+            (Some(404), false),
+            (Some(413), false),
+        ] {
+            assert_eq!(retryable(status), retry, "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let waits: Vec<u64> = (0..8).map(|attempt| backoff(attempt).as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn bodies() {
+        let event = event(EventKind::NewDevice(device(
+            "aa:bb:cc:dd:ee:ff",
+            "192.168.1.7",
+            "eth0",
+        )));
+        let raw: Value = serde_json::from_str(&WebhookFormat::Raw.body(&event)).unwrap();
+        assert_eq!(raw["event"], "new-device");
+        assert_eq!(raw["ip"], "192.168.1.7");
+        assert_eq!(raw["time"], "2022-08-01T10:00:00.000Z");
+        assert_eq!(WebhookFormat::Raw.body(&event), event.to_json());
+
+        let slack: Value = serde_json::from_str(&WebhookFormat::Slack.body(&event)).unwrap();
+        assert_eq!(
+            slack,
+            json!({ "text": "*ARP Watch* New device: [aa:bb:cc:dd:ee:ff] @ 192.168.1.7 on eth0" })
+        );
+        let mattermost: Value =
+            serde_json::from_str(&WebhookFormat::Mattermost.body(&event)).unwrap();
+        assert_eq!(
+            mattermost,
+            json!({
+                "username": "arp-watch",
+                "text": "**ARP Watch** New device: [aa:bb:cc:dd:ee:ff] @ 192.168.1.7 on eth0",
+            })
+        );
+    }
+}
@@ -1,111 +1,762 @@
 use log::{error, info};
 use pnet_datalink::Channel::Ethernet;
-use pnet_datalink::NetworkInterface;
-use std::{error::Error, fs::File, io::Read, sync::mpsc::Sender, thread, time::Duration};
+use pnet_datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    net::Ipv4Addr,
+    sync::{
+        atomic::Ordering,
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::structs::{
-    arp::{ARPOperation, ArpPacket, ArpPacketBuilder},
-    net::{IpAddr, MacAddr},
+use crate::{
+    metrics::{PARSE_ERRORS, PROBES_SENT},
+    pacing::{Pacer, Replies},
+    structs::{
+        arp::{ARPOperation, ArpPacket, CapturedPacket},
+        net::{IpAddr, IpRange, MacAddr},
+        queue::BoundedSender,
+    },
 };
 
-pub fn sniff(interface_name: &str, app_tx: Option<Sender<ArpPacket>>) {
-    let interface_name_match = |iface: &NetworkInterface| iface.name == interface_name;
+pub type Channel = (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>);
 
-    let interfaces = pnet_datalink::interfaces();
-    let interface = interfaces
+pub fn find_interface(interface_name: &str) -> Result<NetworkInterface, Box<dyn Error>> {
+    pnet_datalink::interfaces()
         .into_iter()
-        .filter(interface_name_match)
-        .next()
-        .expect(format!("Interface not found: {}", interface_name).as_str());
-
-    let (mut tx, mut rx) = match pnet_datalink::channel(&interface, Default::default()) {
-        Ok(Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => panic!("Unhandled channel type"),
-        Err(e) => panic!(
+        .find(|iface| iface.name == interface_name)
+        .ok_or_else(|| {
+            InterfaceError {
+                iface: interface_name.to_owned(),
+                cause: "not found".to_owned(),
+            }
+            .into()
+        })
+}
+
+pub fn open_channel(
+    interface: &NetworkInterface,
+    config: pnet_datalink::Config,
+) -> Result<Channel, Box<dyn Error>> {
+    match pnet_datalink::channel(interface, config) {
+        Ok(Ethernet(tx, rx)) => Ok((tx, rx)),
+        Ok(_) => Err("Unhandled channel type".into()),
+        Err(e) => Err(format!(
             "An error occurred when creating the datalink channel: {}",
             e
-        ),
-    };
+        )
+        .into()),
+    }
+}
+
+/// Ethernet source of a frame
+fn frame_source(frame: &[u8]) -> Option<MacAddr> {
+    MacAddr::new(frame.get(6..12)?).ok()
+}
 
-    let mut app_tx_th: Option<Sender<ArpPacket>> = None;
-    if let Some(ref app) = app_tx {
-        app_tx_th = Some(app.clone());
+/// Returns the ARP payload of an ethernet frame, if it carries one
+pub fn parse_frame(frame: &[u8]) -> Option<ArpPacket> {
+    if frame.len() < 42 || frame[12..14] != [0x08, 0x06] {
+        return None;
     }
-    thread::spawn(move || {
-        let mut i = 0;
-        let mut local_mac = local_mac().unwrap();
-        let local_ip = local_ip(&interface).unwrap();
-        loop {
-            let target_ip = IpAddr::new(&[192, 168, 1, i]).unwrap();
-            if local_ip == target_ip {
-                i = i.checked_add(1).unwrap_or(0);
-                continue;
-            }
+    match ArpPacket::from(&frame[14..]) {
+        Ok(packet) => Some(packet),
+        Err(_) => {
+            PARSE_ERRORS.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
 
-            let mut broadcast_mac = MacAddr::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap();
-            let packet = ArpPacketBuilder::default()
-                .sender(local_mac.clone(), local_ip.clone())
-                .target(
-                    broadcast_mac.clone(),
-                    IpAddr::new(&[192, 168, 1, i]).unwrap(),
-                )
-                .operation(ARPOperation::Request)
-                .build();
-            i = i.checked_add(1).unwrap_or(0);
-
-            if let None = app_tx_th {
-                info!(
-                    "Sending:\n{:?}",
-                    packet.raw(&mut local_mac, &mut broadcast_mac).as_slice(),
-                );
-            }
-            tx.send_to(
-                packet.raw(&mut local_mac, &mut broadcast_mac).as_slice(),
-                None,
-            );
+// Scan requests not captured back within this delay are forgotten
+const OWN_REQUEST_TTL: Duration = Duration::from_secs(1);
+// Forgotten requests are only swept once there are that many
+const OWN_REQUESTS_SWEEP: usize = 1024;
+
+pub const DEFAULT_SCAN_RATE: f64 = 10.0;
+pub const DEFAULT_MIN_SCAN_RATE: f64 = 1.0;
+// Longest pause between two sweeps, a week
+pub const MAX_SCAN_INTERVAL: Duration = Duration::from_secs(7 * 24 * 3600);
+// Largest range swept, a /16, wider interface networks are swept around the local address
+pub const MAX_SWEEP_ADDRESSES: u64 = 65536;
+// Progress of a sweep is reported at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+// Wait after a capture error, doubling while errors go on, e.g. the interface is down
+const CAPTURE_RETRY: Duration = Duration::from_millis(10);
+const MAX_CAPTURE_RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    // Ranges to sweep, the networks of the interface when empty
+    pub ranges: Vec<IpRange>,
+    // Requests sent per second
+    pub rate: f64,
+    // Requests that may be sent back to back after a pause
+    pub burst: u32,
+    // Pause between two sweeps when watching
+    pub interval: Duration,
+    // Fraction by which each wait is randomized, from 0 to 1
+    pub jitter: f64,
+    // Slow down while hosts that answered before stop answering
+    pub adaptive: bool,
+    // Lowest rate the adaptive mode slows down to
+    pub min_rate: f64,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            ranges: vec![],
+            rate: DEFAULT_SCAN_RATE,
+            burst: 1,
+            interval: Duration::ZERO,
+            jitter: 0.0,
+            adaptive: false,
+            min_rate: DEFAULT_MIN_SCAN_RATE,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Checked before any scanner starts, so that bad options stop the program
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(range) = self
+            .ranges
+            .iter()
+            .find(|range| range.size() > MAX_SWEEP_ADDRESSES)
+        {
+            return Err(format!(
+                "Scan range {} too large: {} addresses, at most {}",
+                range,
+                range.size(),
+                MAX_SWEEP_ADDRESSES
+            ));
+        }
+        self.pacer()?;
+        if self.interval > MAX_SCAN_INTERVAL {
+            return Err(format!("Invalid scan interval: {:?}", self.interval));
+        }
+        Ok(())
+    }
+
+    fn pacer(&self) -> Result<Pacer, String> {
+        Pacer::new(
+            self.rate,
+            self.burst,
+            self.jitter,
+            self.adaptive,
+            self.min_rate,
+        )
+    }
+}
+
+/// Orders sent to the scanner of an interface while it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanCommand {
+    // Start over from the first address of the ranges
+    Rescan,
+    // Sweep the range now, the full sweep starts over once done
+    Sweep(IpRange),
+    // Send a single request, without interrupting the sweep
+    Probe(IpAddr),
+}
+
+/// Where the scanner of an interface is at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanProgress {
+    pub iface: String,
+    // What is being swept, "all" for the ranges of the interface
+    pub target: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl ScanProgress {
+    pub fn finished(&self) -> bool {
+        self.done >= self.total
+    }
+}
 
-            thread::sleep(Duration::from_millis(100));
+impl Display for ScanProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.finished() {
+            write!(f, "{}: {} done", self.iface, self.target)
+        } else {
+            write!(
+                f,
+                "{}: {} {}/{} ({}%)",
+                self.iface,
+                self.target,
+                self.done,
+                self.total,
+                self.done * 100 / self.total.max(1)
+            )
+        }
+    }
+}
+
+/// Watches the interface, sweeping its ranges over and over in the background
+pub fn sniff(
+    interface_name: &str,
+    app_tx: BoundedSender<CapturedPacket>,
+    options: ScanOptions,
+    commands: Receiver<ScanCommand>,
+    progress: Sender<ScanProgress>,
+) -> Result<(), Box<dyn Error>> {
+    let interface = find_interface(interface_name)?;
+    let mut scanner = Scanner::new(&interface, &options)?;
+    scanner.commands = Some(commands);
+    scanner.progress = Some(progress);
+    let replies = scanner.replies.clone();
+    let own = scanner.own.clone();
+    let (mut tx, mut rx) = open_channel(&interface, Default::default())?;
+
+    let app_tx_th = app_tx.clone();
+    thread::spawn(move || {
+        if let Err(e) = scanner.run(&mut *tx, &app_tx_th, options.interval) {
+            error!("Scan of {} stopped: {}", scanner.interface.name, e);
         }
     });
+    capture(
+        interface_name,
+        &mut *rx,
+        &app_tx,
+        Some(&own),
+        Some(&replies),
+    );
+    Ok(())
+}
+
+/// Sweeps the ranges of the interface once, replies are captured in the background
+pub fn scan(
+    interface_name: &str,
+    app_tx: BoundedSender<CapturedPacket>,
+    options: ScanOptions,
+) -> Result<(), Box<dyn Error>> {
+    let interface = find_interface(interface_name)?;
+    let mut scanner = Scanner::new(&interface, &options)?;
+    let (mut tx, mut rx) = open_channel(&interface, Default::default())?;
+
+    let capture_tx = app_tx.clone();
+    let iface = interface_name.to_owned();
+    let replies = scanner.replies.clone();
+    let own = scanner.own.clone();
+    thread::spawn(move || capture(&iface, &mut *rx, &capture_tx, Some(&own), Some(&replies)));
+    scanner.sweep(&mut *tx, &app_tx)
+}
+
+/// Passively watches the interface, without scanning it
+pub fn listen(
+    interface_name: &str,
+    app_tx: BoundedSender<CapturedPacket>,
+) -> Result<(), Box<dyn Error>> {
+    let interface = find_interface(interface_name)?;
+    let own = local_mac(&interface).ok().map(OwnFrames::new);
+    let (_, mut rx) = open_channel(&interface, Default::default())?;
+    capture(interface_name, &mut *rx, &app_tx, own.as_ref(), None);
+    Ok(())
+}
+
+/**
+Frames sent on an interface by this process. Capture sockets may see them
+come back, they are told apart from the traffic of the segment with this.
+*/
+#[derive(Debug)]
+pub struct OwnFrames {
+    mac: MacAddr,
+    // Targets of the scan requests sent, until captured back
+    requests: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl OwnFrames {
+    pub fn new(mac: MacAddr) -> OwnFrames {
+        OwnFrames {
+            mac,
+            requests: Mutex::default(),
+        }
+    }
+
+    /// Records a scan request, handed to the app when sent
+    pub fn request(&self, target: IpAddr) {
+        let Ok(mut requests) = self.requests.lock() else {
+            return;
+        };
+        if requests.len() >= OWN_REQUESTS_SWEEP {
+            requests.retain(|_, at| at.elapsed() < OWN_REQUEST_TTL);
+        }
+        requests.insert(target, Instant::now());
+    }
+
+    /// Whether the captured frame is one of ours, already accounted for.
+    /// The kernel requests from our mac are not, only the scan ones.
+    pub fn owns(&self, source: &MacAddr, packet: &ArpPacket) -> bool {
+        if *source != self.mac {
+            return false;
+        }
+        // Corrective replies announce the mac of a protected host from ours
+        if packet.sender_mac != self.mac {
+            return true;
+        }
+        if !matches!(packet.operation, ARPOperation::Request) {
+            return false;
+        }
+        let Ok(mut requests) = self.requests.lock() else {
+            return false;
+        };
+        requests
+            .remove(&packet.target_ip)
+            .map(|at| at.elapsed() < OWN_REQUEST_TTL)
+            .unwrap_or(false)
+    }
+}
+
+/// Forwards every ARP packet of the interface to the app, returns once the app is gone
+pub fn capture(
+    interface_name: &str,
+    rx: &mut dyn DataLinkReceiver,
+    app_tx: &BoundedSender<CapturedPacket>,
+    own: Option<&OwnFrames>,
+    replies: Option<&Replies>,
+) {
+    let mut failures = 0;
     loop {
         match rx.next() {
-            Ok(packet) => {
-                if packet.len() < 41 && packet[12..14] != [08, 06] {
-                    continue;
+            Ok(frame) => {
+                if failures > 0 {
+                    info!("Capturing on {} again", interface_name);
+                    failures = 0;
                 }
+                if let Some(packet) = parse_frame(frame) {
+                    if let (Some(own), Some(source)) = (own, frame_source(frame)) {
+                        if own.owns(&source, &packet) {
+                            continue;
+                        }
+                    }
+                    if let Some(replies) = replies {
+                        replies.observe(&packet);
+                    }
+                    let capture = CapturedPacket::new(packet, interface_name, false);
+                    if app_tx.send(capture).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                let wait = capture_retry(failures);
+                error!(
+                    "Can't capture on {}: {}, retrying in {:?}",
+                    interface_name, e, wait
+                );
+                failures += 1;
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Wait before capturing again after `failures` errors in a row
+fn capture_retry(failures: u32) -> Duration {
+    CAPTURE_RETRY
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_CAPTURE_RETRY)
+}
+
+pub struct Scanner {
+    pub interface: NetworkInterface,
+    local_mac: MacAddr,
+    local_ips: Vec<IpAddr>,
+    ranges: Vec<IpRange>,
+    pacer: Pacer,
+    // Replies to our requests, fed by the capture thread
+    pub replies: Arc<Replies>,
+    // Requests sent, for the capture thread to skip
+    pub own: Arc<OwnFrames>,
+    pub commands: Option<Receiver<ScanCommand>>,
+    pub progress: Option<Sender<ScanProgress>>,
+}
+
+impl Scanner {
+    pub fn new(
+        interface: &NetworkInterface,
+        options: &ScanOptions,
+    ) -> Result<Scanner, Box<dyn Error>> {
+        options.validate()?;
+        let local_mac = local_mac(interface)?;
+        let local_ips = local_ips(interface);
+        if local_ips.is_empty() {
+            return Err(Box::new(InterfaceError {
+                iface: interface.name.clone(),
+                cause: "no ipv4 address".to_owned(),
+            }));
+        }
+        let ranges = if options.ranges.is_empty() {
+            interface_ranges(interface)
+                .iter()
+                .map(|network| {
+                    let around = local_ips
+                        .iter()
+                        .find(|ip| network.contains(ip))
+                        .unwrap_or(&network.start);
+                    let clamped = clamp_sweep(network, around);
+                    if clamped != *network {
+                        info!(
+                            "Sweeping {} of {} on {}, at most {} addresses",
+                            clamped, network, interface.name, MAX_SWEEP_ADDRESSES
+                        );
+                    }
+                    clamped
+                })
+                .collect()
+        } else {
+            options.ranges.clone()
+        };
+        // Interface networks are clamped, given ranges were validated
+        let pacer = options.pacer()?;
+
+        Ok(Scanner {
+            interface: interface.clone(),
+            local_mac,
+            local_ips,
+            ranges,
+            pacer,
+            replies: Arc::new(Replies::new(local_mac)),
+            own: Arc::new(OwnFrames::new(local_mac)),
+            commands: None,
+            progress: None,
+        })
+    }
 
-                if let Ok(packet) = ArpPacket::from(&packet[14..]) {
-                    if let Some(ref app) = app_tx {
-                        app.send(packet).unwrap();
-                    } else {
-                        info!("{:?}", packet);
+    /// Sweeps the ranges over and over, `interval` apart, running commands as they come
+    pub fn run(
+        &mut self,
+        tx: &mut dyn DataLinkSender,
+        app_tx: &BoundedSender<CapturedPacket>,
+        interval: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut command = ScanCommand::Rescan;
+        let mut next_sweep = Instant::now();
+        loop {
+            let interrupted = match command {
+                ScanCommand::Rescan => {
+                    let ranges = self.ranges.clone();
+                    let interrupted = self.sweep_ranges("all", &ranges, tx, app_tx)?;
+                    if interrupted.is_none() {
+                        next_sweep = Instant::now() + self.pacer.jittered(interval);
                     }
+                    interrupted
+                }
+                ScanCommand::Sweep(range) if range.size() > MAX_SWEEP_ADDRESSES => {
+                    error!(
+                        "Sweep of {} on {} refused: {} addresses, at most {}",
+                        range,
+                        self.interface.name,
+                        range.size(),
+                        MAX_SWEEP_ADDRESSES
+                    );
+                    None
+                }
+                ScanCommand::Sweep(range) => {
+                    self.sweep_ranges(&range.to_string(), &[range], tx, app_tx)?
+                }
+                ScanCommand::Probe(ip) => {
+                    self.request(ip, tx, app_tx)?;
+                    self.report(&ip.to_string(), 1, 1);
+                    None
+                }
+            };
+            command = match interrupted {
+                Some(command) => command,
+                None => self.wait_command(next_sweep).unwrap_or(ScanCommand::Rescan),
+            };
+        }
+    }
+
+    /// Sends a request to every address of the ranges once
+    pub fn sweep(
+        &mut self,
+        tx: &mut dyn DataLinkSender,
+        app_tx: &BoundedSender<CapturedPacket>,
+    ) -> Result<(), Box<dyn Error>> {
+        let ranges = self.ranges.clone();
+        self.sweep_ranges("all", &ranges, tx, app_tx)?;
+        Ok(())
+    }
+
+    /// Returns the command that interrupted the sweep, probes are sent in between
+    fn sweep_ranges(
+        &mut self,
+        target: &str,
+        ranges: &[IpRange],
+        tx: &mut dyn DataLinkSender,
+        app_tx: &BoundedSender<CapturedPacket>,
+    ) -> Result<Option<ScanCommand>, Box<dyn Error>> {
+        let total = ranges.iter().map(IpRange::size).sum();
+        let mut done = 0;
+        let mut reported = Instant::now();
+        self.report(target, done, total);
+        for range in ranges.iter() {
+            for target_ip in range.iter() {
+                match self.next_command() {
+                    Some(ScanCommand::Probe(ip)) => self.request(ip, tx, app_tx)?,
+                    Some(command) => return Ok(Some(command)),
+                    None => (),
+                }
+                done += 1;
+                if !self.local_ips.contains(&target_ip) {
+                    self.request(target_ip, tx, app_tx)?;
+                }
+                if reported.elapsed() >= PROGRESS_INTERVAL {
+                    self.report(target, done, total);
+                    reported = Instant::now();
+                }
+            }
+        }
+        self.report(target, total, total);
+        Ok(None)
+    }
+
+    /// Sends a request for the address, as fast as the pacing allows
+    fn request(
+        &mut self,
+        target_ip: IpAddr,
+        tx: &mut dyn DataLinkSender,
+        app_tx: &BoundedSender<CapturedPacket>,
+    ) -> Result<(), Box<dyn Error>> {
+        let local_ip = local_ip_for(&self.interface, &target_ip)?;
+        self.pacer.acquire();
+        let packet = ArpPacket::request(self.local_mac, local_ip, target_ip);
+
+        let raw = packet.raw_padded(&self.local_mac, &MacAddr::BROADCAST)?;
+        // Correlated from here, whether or not the capture sees them come back
+        self.own.request(target_ip);
+        let _ = app_tx.send(CapturedPacket::new(packet, &self.interface.name, true));
+        if let Some(Err(e)) = tx.send_to(raw.as_slice(), None) {
+            return Err(Box::new(e));
+        }
+        PROBES_SENT.fetch_add(1, Ordering::Relaxed);
+        self.pacer.sent(target_ip, &self.replies);
+        Ok(())
+    }
+
+    fn report(&self, target: &str, done: u64, total: u64) {
+        if let Some(ref progress) = self.progress {
+            let _ = progress.send(ScanProgress {
+                iface: self.interface.name.clone(),
+                target: target.to_owned(),
+                done,
+                total,
+            });
+        }
+    }
+
+    /// Waits for a command until the deadline
+    fn wait_command(&self, deadline: Instant) -> Option<ScanCommand> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.commands {
+            Some(ref commands) => match commands.recv_timeout(timeout) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(timeout);
+                    None
                 }
+            },
+            None => {
+                thread::sleep(timeout);
+                None
             }
-            Err(e) => error!("Error occurred while catching packets {}", e),
         }
     }
+
+    fn next_command(&self) -> Option<ScanCommand> {
+        self.commands.as_ref()?.try_recv().ok()
+    }
 }
 
-pub fn local_mac() -> Result<MacAddr, Box<dyn Error>> {
-    // TODO remove hardcoded iface name
-    let mut f = File::open("/sys/class/net/wlan0/address").unwrap();
-    let mut content = String::new();
-    f.read_to_string(&mut content).unwrap();
-    let content = &content[..17];
-    let num: Vec<u8> = content
-        .split(":")
-        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
-        .collect();
-    let mac = MacAddr::new(num.as_slice())?;
+/// Host ranges of every ipv4 network of the interface
+pub fn interface_ranges(iface: &NetworkInterface) -> Vec<IpRange> {
+    iface
+        .ips
+        .iter()
+        .filter(|network| network.is_ipv4() && network.prefix() < 32)
+        .filter_map(|network| {
+            format!("{}/{}", network.ip(), network.prefix())
+                .parse()
+                .ok()
+        })
+        .collect()
+}
+
+/// The range itself when small enough to sweep, otherwise the aligned block of
+/// MAX_SWEEP_ADDRESSES addresses of it holding `around`
+pub fn clamp_sweep(range: &IpRange, around: &IpAddr) -> IpRange {
+    if range.size() <= MAX_SWEEP_ADDRESSES {
+        return range.clone();
+    }
+    let around = if range.contains(around) {
+        *around
+    } else {
+        range.start
+    };
+    let mask = !(MAX_SWEEP_ADDRESSES as u32 - 1);
+    let around = u32::from(Ipv4Addr::from(around));
+    let start = u32::from(Ipv4Addr::from(range.start)).max(around & mask);
+    let end = u32::from(Ipv4Addr::from(range.end)).min(around | !mask);
+    IpRange {
+        start: IpAddr::from(Ipv4Addr::from(start)),
+        end: IpAddr::from(Ipv4Addr::from(end)),
+    }
+}
+
+#[derive(Debug)]
+pub struct InterfaceError {
+    pub iface: String,
+    pub cause: String,
+}
+
+impl Display for InterfaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interface {}: {}", self.iface, self.cause)
+    }
+}
+
+impl Error for InterfaceError {}
+
+/// Mac address of the interface, read from sysfs when the interface doesn't report it
+pub fn local_mac(iface: &NetworkInterface) -> Result<MacAddr, Box<dyn Error>> {
+    if let Some(mac) = iface.mac {
+        if !mac.is_zero() {
+            return Ok(MacAddr::from(mac));
+        }
+    }
+
+    let path = format!("/sys/class/net/{}/address", iface.name);
+    let content = fs::read_to_string(&path).map_err(|e| InterfaceError {
+        iface: iface.name.clone(),
+        cause: format!("no mac address ({}: {})", path, e),
+    })?;
+    let mac: MacAddr = content.parse().map_err(|_| InterfaceError {
+        iface: iface.name.clone(),
+        cause: format!("invalid mac address in {}: {}", path, content.trim()),
+    })?;
     Ok(mac)
 }
 
-pub fn local_ip(iface: &NetworkInterface) -> Result<IpAddr, Box<dyn Error>> {
-    match iface.ips[0].ip() {
-        std::net::IpAddr::V4(ip) => {
-            return Ok(IpAddr::new(ip.octets().as_slice()).unwrap());
+/// Every ipv4 address of the interface
+pub fn local_ips(iface: &NetworkInterface) -> Vec<IpAddr> {
+    iface
+        .ips
+        .iter()
+        .filter_map(|network| match network.ip() {
+            std::net::IpAddr::V4(ip) => Some(IpAddr::from(ip)),
+            std::net::IpAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+/// Address to send from when talking to `target`: the one whose network contains it,
+/// or the first ipv4 address of the interface
+pub fn local_ip_for(iface: &NetworkInterface, target: &IpAddr) -> Result<IpAddr, Box<dyn Error>> {
+    let target = Ipv4Addr::from(*target);
+    // Every ipv4 address, along with whether its network contains the target
+    let candidates: Vec<(Ipv4Addr, bool)> = iface
+        .ips
+        .iter()
+        .filter_map(|network| match network.ip() {
+            std::net::IpAddr::V4(ip) => Some((ip, network.contains(target.into()))),
+            std::net::IpAddr::V6(_) => None,
+        })
+        .collect();
+    let (ip, _) = candidates
+        .iter()
+        .find(|(_, contains)| *contains)
+        .or_else(|| candidates.first())
+        .ok_or_else(|| InterfaceError {
+            iface: iface.name.clone(),
+            cause: "no ipv4 address".to_owned(),
+        })?;
+    Ok(IpAddr::from(*ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn clamp_small_range_kept() {
+        let network = range("192.168.1.0/24");
+        let around = "192.168.1.20".parse().unwrap();
+        assert_eq!(clamp_sweep(&network, &around), network);
+    }
+
+    #[test]
+    fn clamp_wide_network_around_address() {
+        let network = range("10.0.0.0/8");
+        let around = "10.42.3.4".parse().unwrap();
+        let clamped = clamp_sweep(&network, &around);
+        assert_eq!(clamped, range("10.42.0.0-10.42.255.255"));
+        assert_eq!(clamped.size(), MAX_SWEEP_ADDRESSES);
+        // The network address stays out of the first block
+        let around = "10.0.0.9".parse().unwrap();
+        assert_eq!(
+            clamp_sweep(&network, &around),
+            range("10.0.0.1-10.0.255.255")
+        );
+    }
+
+    #[test]
+    fn clamp_outside_address_uses_start() {
+        let network = range("10.0.0.0/15");
+        let around = "192.168.1.1".parse().unwrap();
+        assert_eq!(
+            clamp_sweep(&network, &around),
+            range("10.0.0.1-10.0.255.255")
+        );
+    }
+
+    #[test]
+    fn options_validated() {
+        assert!(ScanOptions::default().validate().is_ok());
+        let invalid = [
+            ScanOptions {
+                rate: 0.0,
+                ..ScanOptions::default()
+            },
+            ScanOptions {
+                min_rate: f64::NAN,
+                ..ScanOptions::default()
+            },
+            ScanOptions {
+                interval: MAX_SCAN_INTERVAL + Duration::from_secs(1),
+                ..ScanOptions::default()
+            },
+            ScanOptions {
+                ranges: vec!["10.0.0.0/8".parse().unwrap()],
+                ..ScanOptions::default()
+            },
+        ];
+        for options in invalid {
+            assert!(options.validate().is_err(), "{:?}", options);
         }
-        std::net::IpAddr::V6(_) => todo!(),
+    }
+
+    #[test]
+    fn capture_retry_backs_off() {
+        assert_eq!(capture_retry(0), CAPTURE_RETRY);
+        assert_eq!(capture_retry(1), CAPTURE_RETRY * 2);
+        assert_eq!(capture_retry(3), CAPTURE_RETRY * 8);
+        assert_eq!(capture_retry(9), MAX_CAPTURE_RETRY);
+        assert_eq!(capture_retry(u32::MAX), MAX_CAPTURE_RETRY);
     }
 }
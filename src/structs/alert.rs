@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::{fmt::Display, time::SystemTime};
+
+use super::net::Device;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    // A gratuitous ARP claims an ip bound to another mac
+    GratuitousConflict,
+    // A host emits gratuitous ARPs at an abnormal rate
+    GratuitousStorm,
+    // A reply that no observed request asked for, typical of cache poisoning
+    UnsolicitedReply,
+    // The same mac answers on two interfaces
+    MacOnMultipleSegments,
+    // A protected host ip is now bound to another mac
+    ProtectedHostChanged,
+    // An ip answers from another mac than before
+    ChangedEthernetAddress,
+    // An ip answers again from the mac it had before the current one
+    FlipFlop,
+    // The kernel ARP cache of this host holds another mac than the trusted one
+    PoisonedKernelCache,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub device: Device,
+    pub previous: Option<Device>,
+    // Last time the previous device was seen
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_seen"
+    )]
+    pub previous_seen: Option<SystemTime>,
+    // Acknowledged by an operator
+    #[serde(skip)]
+    pub acked: bool,
+}
+
+impl Alert {
+    pub fn new(kind: AlertKind, device: Device, previous: Option<Device>) -> Alert {
+        Alert {
+            kind,
+            device,
+            previous,
+            previous_seen: None,
+            acked: false,
+        }
+    }
+}
+
+impl AlertKind {
+    pub const ALL: [AlertKind; 8] = [
+        AlertKind::GratuitousConflict,
+        AlertKind::GratuitousStorm,
+        AlertKind::UnsolicitedReply,
+        AlertKind::MacOnMultipleSegments,
+        AlertKind::ProtectedHostChanged,
+        AlertKind::ChangedEthernetAddress,
+        AlertKind::FlipFlop,
+        AlertKind::PoisonedKernelCache,
+    ];
+
+    /// Name used in the config and in machine readable outputs
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::GratuitousConflict => "gratuitous-conflict",
+            AlertKind::GratuitousStorm => "gratuitous-storm",
+            AlertKind::UnsolicitedReply => "unsolicited-reply",
+            AlertKind::MacOnMultipleSegments => "mac-on-multiple-segments",
+            AlertKind::ProtectedHostChanged => "protected-host-changed",
+            AlertKind::ChangedEthernetAddress => "changed-ethernet-address",
+            AlertKind::FlipFlop => "flip-flop",
+            AlertKind::PoisonedKernelCache => "poisoned-kernel-cache",
+        }
+    }
+}
+
+impl Display for AlertKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AlertKind::GratuitousConflict => "Gratuitous ARP conflict",
+            AlertKind::GratuitousStorm => "Gratuitous ARP storm",
+            AlertKind::UnsolicitedReply => "Unsolicited ARP reply",
+            AlertKind::MacOnMultipleSegments => "MAC seen on multiple segments",
+            AlertKind::ProtectedHostChanged => "Protected host changed",
+            AlertKind::ChangedEthernetAddress => "Changed ethernet address",
+            AlertKind::FlipFlop => "Flip flop",
+            AlertKind::PoisonedKernelCache => "Kernel ARP cache poisoned",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.previous {
+            Some(ref previous) => write!(f, "{}: {} (was {})", self.kind, self.device, previous),
+            None => write!(f, "{}: {}", self.kind, self.device),
+        }
+    }
+}
+
+fn serialize_seen<S: Serializer>(
+    seen: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match seen {
+        Some(seen) => serializer.collect_str(&humantime::format_rfc3339_millis(*seen)),
+        None => serializer.serialize_none(),
+    }
+}
@@ -1,7 +1,13 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, time::Instant};
 
 use super::net::{IpAddr, MacAddr};
 
+// Address lengths of ethernet over ipv4, the only combination supported
+const MAC_LEN: usize = 6;
+const IP_LEN: usize = 4;
+// Frames are padded up to this size, frame check sequence excluded
+pub const ETHERNET_MIN_FRAME: usize = 60;
+
 /**
 Byte offset by 2
 0 -> Hardware type
@@ -31,7 +37,7 @@ pub enum HardwareType {
 
 #[derive(Debug)]
 pub enum ProtocolType {
-    // [08, 00]
+    // [0x08, 0x00]
     V4,
     V6,
 }
@@ -55,8 +61,8 @@ pub struct ArpPacketBuilder {
     pub target_ip: Option<IpAddr>,
 }
 
-impl ArpPacketBuilder {
-    pub fn default() -> ArpPacketBuilder {
+impl Default for ArpPacketBuilder {
+    fn default() -> ArpPacketBuilder {
         ArpPacketBuilder {
             hardware_type: HardwareType::Ether,
             proto_type: ProtocolType::V4,
@@ -69,7 +75,9 @@ impl ArpPacketBuilder {
             target_ip: None,
         }
     }
+}
 
+impl ArpPacketBuilder {
     pub fn sender(mut self, mac: MacAddr, ip: IpAddr) -> ArpPacketBuilder {
         self.sender_mac = Some(mac);
         self.sender_ip = Some(ip);
@@ -87,18 +95,55 @@ impl ArpPacketBuilder {
         self
     }
 
-    pub fn build(self) -> ArpPacket {
-        ArpPacket {
+    /// Fails on a missing field, or on lengths that don't match ethernet and ipv4 addresses
+    pub fn build(self) -> Result<ArpPacket, ArpBuildError> {
+        if !matches!(self.hardware_type, HardwareType::Ether) {
+            return Err(ArpBuildError::new(format!(
+                "Unsupported hardware type: {:?}",
+                self.hardware_type
+            )));
+        }
+        if !matches!(self.proto_type, ProtocolType::V4) {
+            return Err(ArpBuildError::new(format!(
+                "Unsupported protocol type: {:?}",
+                self.proto_type
+            )));
+        }
+        check_lengths(self.hardware_len, self.proto_len)?;
+        let missing = |field: &str| ArpBuildError::new(format!("Missing {}", field));
+        Ok(ArpPacket {
             hardware_type: self.hardware_type,
             proto_type: self.proto_type,
             hardware_len: self.hardware_len,
             proto_len: self.proto_len,
-            operation: self.operation.unwrap(),
-            sender_mac: self.sender_mac.unwrap(),
-            sender_ip: self.sender_ip.unwrap(),
-            target_mac: self.target_mac.unwrap(),
-            target_ip: self.target_ip.unwrap(),
-        }
+            operation: self.operation.ok_or_else(|| missing("operation"))?,
+            sender_mac: self.sender_mac.ok_or_else(|| missing("sender mac"))?,
+            sender_ip: self.sender_ip.ok_or_else(|| missing("sender ip"))?,
+            target_mac: self.target_mac.ok_or_else(|| missing("target mac"))?,
+            target_ip: self.target_ip.ok_or_else(|| missing("target ip"))?,
+        })
+    }
+}
+
+/// Only ethernet macs and ipv4 addresses get built or parsed
+fn check_lengths(hardware_len: usize, proto_len: usize) -> Result<(), ArpBuildError> {
+    if hardware_len != MAC_LEN || proto_len != IP_LEN {
+        return Err(ArpBuildError::new(format!(
+            "Invalid address lengths: {}/{}, expected {}/{}",
+            hardware_len, proto_len, MAC_LEN, IP_LEN
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ArpBuildError {
+    pub cause: String,
+}
+
+impl ArpBuildError {
+    fn new(cause: String) -> ArpBuildError {
+        ArpBuildError { cause }
     }
 }
 
@@ -115,12 +160,90 @@ pub struct ArpPacket {
     pub target_ip: IpAddr,
 }
 
+/// ARP packet as it went through the wire
+#[derive(Debug)]
+pub struct CapturedPacket {
+    pub packet: ArpPacket,
+    // Interface the packet was captured on
+    pub iface: String,
+    pub at: Instant,
+    // Sent by us and handed to the app directly, its captured copy is skipped
+    pub local: bool,
+}
+
+impl CapturedPacket {
+    pub fn new(packet: ArpPacket, iface: &str, local: bool) -> CapturedPacket {
+        CapturedPacket {
+            packet,
+            iface: iface.to_owned(),
+            at: Instant::now(),
+            local,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ArpParseError {
     pub cause: String,
 }
 
 impl ArpPacket {
+    /// Who has `target_ip`, tell `sender_ip`
+    pub fn request(sender_mac: MacAddr, sender_ip: IpAddr, target_ip: IpAddr) -> ArpPacket {
+        ArpPacket::ether(
+            ARPOperation::Request,
+            (sender_mac, sender_ip),
+            (MacAddr::ZERO, target_ip),
+        )
+    }
+
+    /// `sender_ip` is at `sender_mac`, answering the request of the target
+    pub fn reply(
+        sender_mac: MacAddr,
+        sender_ip: IpAddr,
+        target_mac: MacAddr,
+        target_ip: IpAddr,
+    ) -> ArpPacket {
+        ArpPacket::ether(
+            ARPOperation::Reply,
+            (sender_mac, sender_ip),
+            (target_mac, target_ip),
+        )
+    }
+
+    /// Unsolicited broadcast reply updating the caches of the segment
+    pub fn gratuitous(mac: MacAddr, ip: IpAddr) -> ArpPacket {
+        ArpPacket::ether(ARPOperation::Reply, (mac, ip), (MacAddr::BROADCAST, ip))
+    }
+
+    /// RFC 5227 probe, asks for `target_ip` without claiming any address
+    pub fn probe(sender_mac: MacAddr, target_ip: IpAddr) -> ArpPacket {
+        ArpPacket::request(sender_mac, IpAddr::UNSPECIFIED, target_ip)
+    }
+
+    /// RFC 5227 announcement, a request claiming `ip` for `mac`
+    pub fn announcement(mac: MacAddr, ip: IpAddr) -> ArpPacket {
+        ArpPacket::request(mac, ip, ip)
+    }
+
+    fn ether(
+        operation: ARPOperation,
+        (sender_mac, sender_ip): (MacAddr, IpAddr),
+        (target_mac, target_ip): (MacAddr, IpAddr),
+    ) -> ArpPacket {
+        ArpPacket {
+            hardware_type: HardwareType::Ether,
+            proto_type: ProtocolType::V4,
+            hardware_len: MAC_LEN,
+            proto_len: IP_LEN,
+            operation,
+            sender_mac,
+            sender_ip,
+            target_mac,
+            target_ip,
+        }
+    }
+
     pub fn from(packet: &[u8]) -> Result<ArpPacket, Box<dyn Error>> {
         if packet.len() < 28 {
             return Err(Box::new(ArpParseError {
@@ -128,23 +251,32 @@ impl ArpPacket {
             }));
         }
         // TODO Support other type
-        if &packet[0..2] != [00, 01] {
+        if packet[0..2] != [0x00, 0x01] {
             return Err(Box::new(ArpParseError {
                 cause: format!("Invalid hardware type: {:?}", &packet[0..2]),
             }));
         }
-        if &packet[2..4] != [08, 00] {
+        if packet[2..4] != [0x08, 0x00] {
             return Err(Box::new(ArpParseError {
                 cause: format!("Invalid proto version: {:?}", &packet[2..4]),
             }));
         }
+        if packet[4..6] != [MAC_LEN as u8, IP_LEN as u8] {
+            return Err(Box::new(ArpParseError {
+                cause: format!("Invalid address lengths: {:?}", &packet[4..6]),
+            }));
+        }
 
         let hardware_type = HardwareType::Ether;
         let proto_type = ProtocolType::V4;
         let operation = match &packet[6..8] {
-            [00, 01] => ARPOperation::Request,
-            [00, 02] => ARPOperation::Reply,
-            _ => panic!("Unexpected arp operation"),
+            [0x00, 0x01] => ARPOperation::Request,
+            [0x00, 0x02] => ARPOperation::Reply,
+            op => {
+                return Err(Box::new(ArpParseError {
+                    cause: format!("Unexpected arp operation: {:?}", op),
+                }))
+            }
         };
         let sender_mac = MacAddr::new(&packet[8..14])?;
         let sender_ip = IpAddr::new(&packet[14..18])?;
@@ -153,8 +285,8 @@ impl ArpPacket {
         Ok(ArpPacket {
             hardware_type,
             proto_type,
-            hardware_len: 6,
-            proto_len: 4,
+            hardware_len: MAC_LEN,
+            proto_len: IP_LEN,
             operation,
             sender_mac,
             sender_ip,
@@ -163,24 +295,43 @@ impl ArpPacket {
         })
     }
 
-    pub fn raw(&self, src: &mut MacAddr, dst: &mut MacAddr) -> Vec<u8> {
+    /// Gratuitous ARPs announce the sender binding, sender and target ip are the same
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip && !self.sender_ip.is_unspecified()
+    }
+
+    /// Ethernet frame carrying the packet, from `src` to `dst`.
+    /// Fails when the address lengths don't match the addresses written.
+    pub fn raw(&self, src: &MacAddr, dst: &MacAddr) -> Result<Vec<u8>, ArpBuildError> {
+        check_lengths(self.hardware_len, self.proto_len)?;
         let mut vec: Vec<u8> = vec![];
-        vec.append(&mut dst.field.clone());
-        vec.append(&mut src.field.clone());
-        vec.append(vec![08, 06].as_mut());
-        vec.append(vec![00, 01].as_mut());
-        vec.append(vec![08, 00].as_mut());
-        vec.append(vec![06, 04].as_mut());
+        vec.extend_from_slice(&dst.octets());
+        vec.extend_from_slice(&src.octets());
+        vec.append(vec![0x08, 0x06].as_mut());
+        // Only ethernet and ipv4 get built or parsed
+        vec.append(vec![0x00, 0x01].as_mut());
+        vec.append(vec![0x08, 0x00].as_mut());
+        vec.push(self.hardware_len as u8);
+        vec.push(self.proto_len as u8);
         let op: &[u8; 2] = match self.operation {
-            ARPOperation::Request => &[00, 01],
-            ARPOperation::Reply => &[00, 02],
+            ARPOperation::Request => &[0x00, 0x01],
+            ARPOperation::Reply => &[0x00, 0x02],
         };
         vec.append(&mut op.to_vec());
-        vec.append(&mut self.sender_mac.field.clone());
-        vec.append(&mut self.sender_ip.field.clone());
-        vec.append(&mut self.target_mac.field.clone());
-        vec.append(&mut self.target_ip.field.clone());
-        vec
+        vec.extend_from_slice(&self.sender_mac.octets());
+        vec.extend_from_slice(&self.sender_ip.octets());
+        vec.extend_from_slice(&self.target_mac.octets());
+        vec.extend_from_slice(&self.target_ip.octets());
+        Ok(vec)
+    }
+
+    /// Same as raw, zero padded to the minimum ethernet frame size
+    pub fn raw_padded(&self, src: &MacAddr, dst: &MacAddr) -> Result<Vec<u8>, ArpBuildError> {
+        let mut vec = self.raw(src, dst)?;
+        if vec.len() < ETHERNET_MIN_FRAME {
+            vec.resize(ETHERNET_MIN_FRAME, 0);
+        }
+        Ok(vec)
     }
 }
 
@@ -193,3 +344,145 @@ impl Display for ArpParseError {
     }
 }
 impl Error for ArpParseError {}
+
+impl Display for ArpBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Can't build arp packet: {}", self.cause)
+    }
+}
+impl Error for ArpBuildError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const TARGET: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::new(&[10, 0, 0, last]).unwrap()
+    }
+
+    fn complete() -> ArpPacketBuilder {
+        ArpPacketBuilder::default()
+            .operation(ARPOperation::Reply)
+            .sender(MAC, ip(1))
+            .target(TARGET, ip(2))
+    }
+
+    fn error(builder: ArpPacketBuilder) -> String {
+        builder.build().unwrap_err().cause
+    }
+
+    #[test]
+    fn build_complete() {
+        let packet = complete().build().unwrap();
+        assert!(matches!(packet.operation, ARPOperation::Reply));
+        assert_eq!(packet.sender_mac, MAC);
+        assert_eq!(packet.target_ip, ip(2));
+    }
+
+    #[test]
+    fn build_missing_fields() {
+        assert_eq!(error(ArpPacketBuilder::default()), "Missing operation");
+        let builder = ArpPacketBuilder::default().operation(ARPOperation::Request);
+        assert_eq!(error(builder), "Missing sender mac");
+        let builder = ArpPacketBuilder::default()
+            .operation(ARPOperation::Request)
+            .sender(MAC, ip(1));
+        assert_eq!(error(builder), "Missing target mac");
+    }
+
+    #[test]
+    fn build_unsupported() {
+        let mut builder = complete();
+        builder.hardware_len = 8;
+        assert_eq!(error(builder), "Invalid address lengths: 8/4, expected 6/4");
+        let mut builder = complete();
+        builder.proto_len = 16;
+        assert_eq!(
+            error(builder),
+            "Invalid address lengths: 6/16, expected 6/4"
+        );
+        let mut builder = complete();
+        builder.hardware_type = HardwareType::HDLC;
+        assert_eq!(error(builder), "Unsupported hardware type: HDLC");
+        let mut builder = complete();
+        builder.proto_type = ProtocolType::V6;
+        assert_eq!(error(builder), "Unsupported protocol type: V6");
+    }
+
+    #[test]
+    fn raw_frame() {
+        let raw = ArpPacket::request(MAC, ip(1), ip(2))
+            .raw(&MAC, &MacAddr::BROADCAST)
+            .unwrap();
+        assert_eq!(raw.len(), 42);
+        assert_eq!(raw[0..6], MacAddr::BROADCAST.octets());
+        assert_eq!(raw[6..12], MAC.octets());
+        assert_eq!(raw[12..14], [0x08, 0x06]);
+        assert_eq!(raw[14..22], [0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn raw_inconsistent_lengths() {
+        let mut packet = ArpPacket::request(MAC, ip(1), ip(2));
+        packet.hardware_len = 8;
+        assert!(packet.raw(&MAC, &MacAddr::BROADCAST).is_err());
+        packet.hardware_len = MAC_LEN;
+        packet.proto_len = 16;
+        assert!(packet.raw_padded(&MAC, &MacAddr::BROADCAST).is_err());
+        packet.proto_len = IP_LEN;
+        assert!(packet.raw(&MAC, &MacAddr::BROADCAST).is_ok());
+    }
+
+    #[test]
+    fn raw_padded_frame() {
+        let packet = ArpPacket::gratuitous(MAC, ip(1));
+        let raw = packet.raw(&MAC, &MacAddr::BROADCAST).unwrap();
+        let padded = packet.raw_padded(&MAC, &MacAddr::BROADCAST).unwrap();
+        assert_eq!(padded.len(), ETHERNET_MIN_FRAME);
+        assert_eq!(padded[..raw.len()], raw[..]);
+        assert!(padded[raw.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        let raw = complete()
+            .build()
+            .unwrap()
+            .raw_padded(&MAC, &TARGET)
+            .unwrap();
+        let packet = ArpPacket::from(&raw[14..]).unwrap();
+        assert!(matches!(packet.operation, ARPOperation::Reply));
+        assert_eq!(packet.sender_mac, MAC);
+        assert_eq!(packet.sender_ip, ip(1));
+        assert_eq!(packet.target_mac, TARGET);
+        assert_eq!(packet.target_ip, ip(2));
+    }
+
+    #[test]
+    fn parse_rejected() {
+        let raw = ArpPacket::probe(MAC, ip(2))
+            .raw(&MAC, &MacAddr::BROADCAST)
+            .unwrap();
+        assert!(ArpPacket::from(&raw[14..41]).is_err());
+        let mut payload = raw[14..].to_vec();
+        payload[4] = 8;
+        assert!(ArpPacket::from(&payload).is_err());
+        let mut payload = raw[14..].to_vec();
+        payload[7] = 3;
+        assert!(ArpPacket::from(&payload).is_err());
+    }
+
+    #[test]
+    fn constructors() {
+        let probe = ArpPacket::probe(MAC, ip(2));
+        assert!(probe.sender_ip.is_unspecified());
+        assert!(!probe.is_gratuitous());
+        assert!(ArpPacket::announcement(MAC, ip(1)).is_gratuitous());
+        let gratuitous = ArpPacket::gratuitous(MAC, ip(1));
+        assert!(gratuitous.is_gratuitous());
+        assert!(gratuitous.target_mac.is_broadcast());
+    }
+}
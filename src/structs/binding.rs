@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use super::net::MacAddr;
+
+// Packets in a row a mac must claim the ip with before the binding is trusted
+const SETTLED_OBSERVATIONS: u32 = 5;
+// Time a binding that never changed must hold before its mac gets pinned
+const VERIFIED_AFTER: Duration = Duration::from_secs(3600);
+
+/// Mac an ip is bound to on a segment, with the one it had before
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub mac: MacAddr,
+    pub previous: Option<MacAddr>,
+    // Packets in a row claiming the current mac
+    pub observations: u32,
+    // When the current mac got bound
+    pub since: Instant,
+}
+
+/// Outcome of a packet claiming an already bound ip
+pub enum Rebind {
+    // Bound to a mac never seen for this ip
+    Changed(MacAddr),
+    // Back to the mac it had before the current one
+    FlipFlop(MacAddr),
+}
+
+impl Binding {
+    pub fn new(mac: MacAddr, at: Instant) -> Binding {
+        Binding {
+            mac,
+            previous: None,
+            observations: 1,
+            since: at,
+        }
+    }
+
+    /// Whether the current mac held long enough to be a reference, rather than
+    /// a rebind in progress
+    pub fn settled(&self) -> bool {
+        self.observations >= SETTLED_OBSERVATIONS
+    }
+
+    /// Whether the mac can go in a permanent kernel entry: the ip was never bound
+    /// to another one, and kept it for VERIFIED_AFTER. Settling only takes a few
+    /// packets, which any running spoofer sends within seconds.
+    pub fn verified(&self, now: Instant) -> bool {
+        self.settled()
+            && self.previous.is_none()
+            && now.saturating_duration_since(self.since) >= VERIFIED_AFTER
+    }
+
+    /// Binds the ip to `mac`, returns the change with the replaced mac if any
+    pub fn rebind(&mut self, mac: &MacAddr, at: Instant) -> Option<Rebind> {
+        if self.mac == *mac {
+            self.observations = self.observations.saturating_add(1);
+            return None;
+        }
+        self.observations = 1;
+        self.since = at;
+        let replaced = std::mem::replace(&mut self.mac, *mac);
+        let flip_flop = self.previous.as_ref() == Some(mac);
+        self.previous = Some(replaced);
+        Some(if flip_flop {
+            Rebind::FlipFlop(replaced)
+        } else {
+            Rebind::Changed(replaced)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGIT: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const SPOOFED: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x66]);
+
+    fn held(mac: MacAddr, at: Instant) -> Binding {
+        let mut binding = Binding::new(mac, at);
+        for _ in 1..SETTLED_OBSERVATIONS {
+            binding.rebind(&mac, at);
+        }
+        binding
+    }
+
+    #[test]
+    fn verified_after_holding() {
+        let start = Instant::now();
+        let binding = held(LEGIT, start);
+        assert!(binding.settled());
+        assert!(!binding.verified(start));
+        assert!(!binding.verified(start + VERIFIED_AFTER - Duration::from_secs(1)));
+        assert!(binding.verified(start + VERIFIED_AFTER));
+    }
+
+    #[test]
+    fn unsettled_not_verified() {
+        let start = Instant::now();
+        let binding = Binding::new(LEGIT, start);
+        assert!(!binding.verified(start + VERIFIED_AFTER));
+    }
+
+    #[test]
+    fn changed_never_verified() {
+        let start = Instant::now();
+        let mut binding = held(LEGIT, start);
+        assert!(matches!(
+            binding.rebind(&SPOOFED, start),
+            Some(Rebind::Changed(LEGIT))
+        ));
+        for _ in 0..SETTLED_OBSERVATIONS {
+            binding.rebind(&SPOOFED, start);
+        }
+        assert!(binding.settled());
+        assert!(!binding.verified(start + VERIFIED_AFTER * 2));
+        // Back to the original mac, still not trusted
+        assert!(matches!(
+            binding.rebind(&LEGIT, start),
+            Some(Rebind::FlipFlop(SPOOFED))
+        ));
+        for _ in 0..SETTLED_OBSERVATIONS {
+            binding.rebind(&LEGIT, start);
+        }
+        assert!(!binding.verified(start + VERIFIED_AFTER * 2));
+    }
+}
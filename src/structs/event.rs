@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::{fmt::Display, time::SystemTime};
+
+use super::{
+    alert::{Alert, AlertKind},
+    net::Device,
+};
+
+/// Everything the detection pipeline reports, as sent to the sinks
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    #[serde(serialize_with = "serialize_time")]
+    pub time: SystemTime,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum EventKind {
+    // Seen for the first time, or again after being gone
+    NewDevice(Device),
+    // A known mac moved to another ip
+    Changed { previous: Device, device: Device },
+    Alert(Alert),
+    // Not seen for a while
+    Gone(Device),
+}
+
+/// Event classes sinks can be restricted to, alert kinds select alerts of that kind
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventClass {
+    NewDevice,
+    Changed,
+    Alert,
+    Gone,
+    #[serde(untagged)]
+    AlertKind(AlertKind),
+}
+
+impl Event {
+    pub fn new(kind: EventKind) -> Event {
+        Event {
+            time: SystemTime::now(),
+            kind,
+        }
+    }
+
+    pub fn is(&self, class: EventClass) -> bool {
+        match (class, &self.kind) {
+            (EventClass::NewDevice, EventKind::NewDevice(_)) => true,
+            (EventClass::Changed, EventKind::Changed { .. }) => true,
+            (EventClass::Alert, EventKind::Alert(_)) => true,
+            (EventClass::AlertKind(kind), EventKind::Alert(alert)) => alert.kind == kind,
+            (EventClass::Gone, EventKind::Gone(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Kebab case name of the event, the alert kind for alerts
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            EventKind::NewDevice(_) => "new-device",
+            EventKind::Changed { .. } => "changed",
+            EventKind::Alert(ref alert) => alert.kind.name(),
+            EventKind::Gone(_) => "gone",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::NewDevice(dev) => write!(f, "New device: {}", dev),
+            EventKind::Changed { previous, device } => {
+                write!(f, "Changed: {} -> {}", previous, device)
+            }
+            EventKind::Alert(alert) => write!(f, "{}", alert),
+            EventKind::Gone(dev) => write!(f, "Gone: {}", dev),
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+fn serialize_time<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
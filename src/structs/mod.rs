@@ -1,4 +1,11 @@
+pub mod alert;
+pub mod binding;
+pub mod event;
 pub mod net;
+pub mod pending;
+pub mod queue;
 pub mod statelist;
+pub mod stats;
+pub mod store;
 
 pub mod arp;
@@ -1,96 +1,330 @@
-use std::{error::Error, fmt::Display};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{error::Error, fmt::Display, net::Ipv4Addr, str::FromStr};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Device {
     pub mac: MacAddr,
     pub ip: IpAddr,
+    // Interface the device was seen on
+    pub iface: String,
 }
 
+/// Identifies a device: the same mac on two segments is two devices
+pub type DeviceKey = (String, MacAddr);
+
 impl Device {
-    // TODO Add error
-    pub fn new(mac: &[u8], ip: &[u8]) -> Result<Device, AddressParseError> {
+    pub fn new(mac: &[u8], ip: &[u8], iface: &str) -> Result<Device, AddressParseError> {
         let mac = MacAddr::new(mac)?;
         let ip = IpAddr::new(ip)?;
-        Ok(Device { mac, ip })
+        Ok(Device {
+            mac,
+            ip,
+            iface: iface.to_owned(),
+        })
     }
 
     pub fn mac(&self) -> &MacAddr {
         &self.mac
     }
+
+    pub fn key(&self) -> DeviceKey {
+        (self.iface.clone(), self.mac)
+    }
 }
 
 impl Display for Device {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{}] @ {}", self.mac, self.ip)
+        write!(f, "[{}] @ {} on {}", self.mac, self.ip, self.iface)
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct MacAddr {
-    pub field: Vec<u8>,
-}
+/// Ethernet address, displayed and parsed as aa:bb:cc:dd:ee:ff
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct MacAddr(pub [u8; 6]);
 
 impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+
     pub fn new(field: &[u8]) -> Result<MacAddr, AddressParseError> {
-        if field.len() != 6 {
+        let octets: [u8; 6] = field.try_into().map_err(|_| AddressParseError)?;
+        Ok(MacAddr(octets))
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::BROADCAST
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("invalid address: {}", s)))
+    }
+}
+
+/// Accepts aa:bb:cc:dd:ee:ff, aa-bb-cc-dd-ee-ff, aabb.ccdd.eeff and aabbccddeeff, in any case
+impl FromStr for MacAddr {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let separator = s.chars().find(|c| matches!(c, ':' | '-'));
+        let digits: String = if let Some(separator) = separator {
+            // Separated bytes, leading zeros may be omitted. The first separator
+            // is used throughout, others are left in the bytes and rejected.
+            let bytes: Vec<&str> = s.split(separator).collect();
+            if bytes.len() != 6 || bytes.iter().any(|byte| byte.is_empty() || byte.len() > 2) {
+                return Err(AddressParseError);
+            }
+            bytes.iter().map(|byte| format!("{:0>2}", byte)).collect()
+        } else if s.contains('.') {
+            let groups: Vec<&str> = s.split('.').collect();
+            if groups.len() != 3 || groups.iter().any(|group| group.len() != 4) {
+                return Err(AddressParseError);
+            }
+            groups.concat()
+        } else {
+            s.to_owned()
+        };
+        if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
             return Err(AddressParseError);
         }
-        Ok(MacAddr {
-            field: field.to_owned(),
-        })
+        let mut octets = [0; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet =
+                u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| AddressParseError)?;
+        }
+        Ok(MacAddr(octets))
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> MacAddr {
+        MacAddr(octets)
+    }
+}
+
+impl From<MacAddr> for [u8; 6] {
+    fn from(addr: MacAddr) -> [u8; 6] {
+        addr.0
+    }
+}
+
+impl From<MacAddr> for Vec<u8> {
+    fn from(addr: MacAddr) -> Vec<u8> {
+        addr.0.to_vec()
+    }
+}
+
+impl From<pnet_datalink::MacAddr> for MacAddr {
+    fn from(addr: pnet_datalink::MacAddr) -> MacAddr {
+        MacAddr(addr.octets())
     }
 }
 
-impl Into<Vec<u8>> for MacAddr {
-    fn into(self) -> Vec<u8> {
-        self.field
+impl From<MacAddr> for pnet_datalink::MacAddr {
+    fn from(addr: MacAddr) -> pnet_datalink::MacAddr {
+        let [a, b, c, d, e, f] = addr.0;
+        pnet_datalink::MacAddr::new(a, b, c, d, e, f)
+    }
+}
+
+impl Serialize for MacAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
 impl Display for MacAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
         write!(
             f,
-            "{:0>2X}::{:0>2X}::{:0>2X}::{:0>2X}::{:0>2X}::{:0>2X}",
-            self.field[0],
-            self.field[1],
-            self.field[2],
-            self.field[3],
-            self.field[4],
-            self.field[5]
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
         )
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct IpAddr {
-    pub field: Vec<u8>,
-}
+/// Ipv4 address, ARP being ipv4 only
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct IpAddr(pub Ipv4Addr);
 
 impl IpAddr {
+    pub const UNSPECIFIED: IpAddr = IpAddr(Ipv4Addr::UNSPECIFIED);
+
     pub fn new(field: &[u8]) -> Result<IpAddr, AddressParseError> {
-        if field.len() != 4 {
-            return Err(AddressParseError);
+        let octets: [u8; 4] = field.try_into().map_err(|_| AddressParseError)?;
+        Ok(IpAddr(Ipv4Addr::from(octets)))
+    }
+
+    pub fn octets(&self) -> [u8; 4] {
+        self.0.octets()
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        self.0.is_unspecified()
+    }
+
+    fn to_u32(self) -> u32 {
+        u32::from(self.0)
+    }
+
+    fn from_u32(value: u32) -> IpAddr {
+        IpAddr(Ipv4Addr::from(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for IpAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("invalid address: {}", s)))
+    }
+}
+
+impl FromStr for IpAddr {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ip: Ipv4Addr = s.trim().parse().map_err(|_| AddressParseError)?;
+        Ok(IpAddr(ip))
+    }
+}
+
+impl From<Ipv4Addr> for IpAddr {
+    fn from(ip: Ipv4Addr) -> IpAddr {
+        IpAddr(ip)
+    }
+}
+
+impl From<IpAddr> for Ipv4Addr {
+    fn from(addr: IpAddr) -> Ipv4Addr {
+        addr.0
+    }
+}
+
+impl From<IpAddr> for std::net::IpAddr {
+    fn from(addr: IpAddr) -> std::net::IpAddr {
+        std::net::IpAddr::V4(addr.0)
+    }
+}
+
+/// Fails for ipv6 addresses
+impl TryFrom<std::net::IpAddr> for IpAddr {
+    type Error = AddressParseError;
+
+    fn try_from(ip: std::net::IpAddr) -> Result<IpAddr, AddressParseError> {
+        match ip {
+            std::net::IpAddr::V4(ip) => Ok(IpAddr(ip)),
+            std::net::IpAddr::V6(_) => Err(AddressParseError),
         }
-        Ok(IpAddr {
-            field: field.to_owned(),
-        })
     }
 }
 
-impl Into<Vec<u8>> for IpAddr {
-    fn into(self) -> Vec<u8> {
-        self.field
+impl From<IpAddr> for Vec<u8> {
+    fn from(addr: IpAddr) -> Vec<u8> {
+        addr.octets().to_vec()
+    }
+}
+
+impl Serialize for IpAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
 impl Display for IpAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}.{}.{}.{}",
-            self.field[0], self.field[1], self.field[2], self.field[3],
-        )
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Inclusive range of ipv4 addresses.
+/// Parsed from `a.b.c.d`, `a.b.c.d-e.f.g.h` or `a.b.c.d/nn`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IpRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        (self.start.to_u32()..=self.end.to_u32()).contains(&ip.to_u32())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> {
+        (self.start.to_u32()..=self.end.to_u32()).map(IpAddr::from_u32)
+    }
+
+    /// Whether every address of `other` is in the range
+    pub fn includes(&self, other: &IpRange) -> bool {
+        self.contains(&other.start) && self.contains(&other.end)
+    }
+
+    /// Number of addresses in the range, 2^32 doesn't fit a 32 bits usize
+    pub fn size(&self) -> u64 {
+        u64::from(self.end.to_u32() - self.start.to_u32()) + 1
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| de::Error::custom(format!("invalid address: {}", s)))
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((start, end)) = s.split_once('-') {
+            let start: IpAddr = start.parse()?;
+            let end: IpAddr = end.parse()?;
+            if start.to_u32() > end.to_u32() {
+                return Err(AddressParseError);
+            }
+            return Ok(IpRange { start, end });
+        }
+        if let Some((network, prefix)) = s.split_once('/') {
+            let network: IpAddr = network.parse()?;
+            let prefix: u32 = prefix.trim().parse().map_err(|_| AddressParseError)?;
+            if prefix > 32 {
+                return Err(AddressParseError);
+            }
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let mut start = network.to_u32() & mask;
+            let mut end = start | !mask;
+            // Network and broadcast addresses are not host addresses
+            if prefix < 31 {
+                start += 1;
+                end -= 1;
+            }
+            return Ok(IpRange {
+                start: IpAddr::from_u32(start),
+                end: IpAddr::from_u32(end),
+            });
+        }
+        let ip: IpAddr = s.parse()?;
+        Ok(IpRange { start: ip, end: ip })
     }
 }
 
@@ -105,3 +339,120 @@ impl Display for AddressParseError {
 }
 
 impl Error for AddressParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0xef]);
+
+    #[test]
+    fn mac_notations() {
+        for s in [
+            "00:1a:2b:3c:4d:ef",
+            "00-1A-2B-3C-4D-EF",
+            "001a.2b3c.4def",
+            "001A2B3C4DEF",
+            "0:1a:2b:3c:4d:ef",
+            " 00:1a:2b:3c:4d:ef\n",
+        ] {
+            assert_eq!(s.parse::<MacAddr>().unwrap(), MAC, "{}", s);
+        }
+    }
+
+    #[test]
+    fn mac_rejected() {
+        for s in [
+            "",
+            "00:1a:2b:3c:4d",
+            "00:1a:2b:3c:4d:ef:01",
+            "00:1a:2b:3c:4d:",
+            "00:1a:2b:3c:4d:eff",
+            "00:1a:2b:3c:4d:eg",
+            "001a.2b3c.4de",
+            "001a.2b3c.4def.0000",
+            "001a2b3c4de",
+            "001a2b3c4def0",
+            "+01a2b3c4def",
+            // Mixed separators
+            "aa:bb-cc:dd-ee:ff",
+            "00-1a:2b:3c:4d:ef",
+            "0:1-:2b:3c:4d:ef",
+        ] {
+            assert!(s.parse::<MacAddr>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn mac_display_roundtrip() {
+        let mac: MacAddr = "AA-BB-CC-DD-EE-FF".parse().unwrap();
+        assert_eq!(mac.to_string(), "aa:bb:cc:dd:ee:ff");
+        assert_eq!(mac.to_string().parse::<MacAddr>().unwrap(), mac);
+    }
+
+    fn range(start: &str, end: &str) -> IpRange {
+        IpRange {
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn range_cidr() {
+        let parsed: IpRange = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(parsed, range("192.168.1.1", "192.168.1.254"));
+        assert_eq!(parsed.size(), 254);
+        let parsed: IpRange = "10.0.0.0/31".parse().unwrap();
+        assert_eq!(parsed, range("10.0.0.0", "10.0.0.1"));
+        let parsed: IpRange = "10.0.0.5/32".parse().unwrap();
+        assert_eq!(parsed, range("10.0.0.5", "10.0.0.5"));
+        let parsed: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(parsed, range("0.0.0.1", "255.255.255.254"));
+        assert_eq!(parsed.size(), (1 << 32) - 2);
+    }
+
+    #[test]
+    fn range_bounds() {
+        let parsed: IpRange = "10.0.0.10-10.0.1.9".parse().unwrap();
+        assert_eq!(parsed, range("10.0.0.10", "10.0.1.9"));
+        assert_eq!(parsed.size(), 256);
+        let parsed: IpRange = "10.0.0.10".parse().unwrap();
+        assert_eq!(parsed, range("10.0.0.10", "10.0.0.10"));
+        assert_eq!(parsed.size(), 1);
+        assert_eq!(parsed.iter().collect::<Vec<_>>(), [parsed.start]);
+    }
+
+    #[test]
+    fn range_rejected() {
+        for s in [
+            "",
+            "10.0.0.9-10.0.0.1",
+            "10.0.0.0/33",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0/24",
+            "10.0.0.1-",
+            "::1",
+        ] {
+            assert!(s.parse::<IpRange>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn range_contains() {
+        let network: IpRange = "10.0.0.0/24".parse().unwrap();
+        assert!(network.contains(&"10.0.0.1".parse().unwrap()));
+        assert!(network.contains(&"10.0.0.254".parse().unwrap()));
+        assert!(!network.contains(&"10.0.0.0".parse().unwrap()));
+        assert!(!network.contains(&"10.0.1.1".parse().unwrap()));
+        assert_eq!(network.to_string(), "10.0.0.1-10.0.0.254");
+    }
+
+    #[test]
+    fn range_includes() {
+        let network: IpRange = "10.0.0.0/24".parse().unwrap();
+        assert!(network.includes(&"10.0.0.1-10.0.0.20".parse().unwrap()));
+        assert!(!network.includes(&"10.0.0.200-10.0.1.20".parse().unwrap()));
+        assert!(!network.includes(&"10.0.0.0".parse().unwrap()));
+    }
+}
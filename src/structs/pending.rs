@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::net::{IpAddr, MacAddr};
+
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub requester: MacAddr,
+    pub at: Instant,
+}
+
+/// Outstanding ARP requests, indexed by the ip being asked for.
+/// Requests are forgotten once answered or after `ttl`.
+pub struct PendingRequests {
+    requests: HashMap<IpAddr, Vec<PendingRequest>>,
+    ttl: Duration,
+}
+
+impl PendingRequests {
+    pub fn new(ttl: Duration) -> PendingRequests {
+        PendingRequests {
+            requests: HashMap::new(),
+            ttl,
+        }
+    }
+
+    pub fn insert(&mut self, asked: IpAddr, requester: MacAddr, at: Instant) {
+        self.requests
+            .entry(asked)
+            .or_default()
+            .push(PendingRequest { requester, at });
+    }
+
+    /// Removes and returns the request answered by a reply from `asked` to `requester`.
+    /// A broadcast reply answers any request for the ip.
+    pub fn answer(&mut self, asked: &IpAddr, requester: &MacAddr) -> Option<PendingRequest> {
+        let requests = self.requests.get_mut(asked)?;
+        let broadcast = requester.is_broadcast();
+        let index = requests
+            .iter()
+            .position(|request| broadcast || request.requester == *requester)?;
+        let request = requests.remove(index);
+        if requests.is_empty() {
+            self.requests.remove(asked);
+        }
+        Some(request)
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.requests.retain(|_, requests| {
+            requests.retain(|request| now.duration_since(request.at) <= ttl);
+            !requests.is_empty()
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(3);
+    const REQUESTER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const OTHER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn answered_once() {
+        let now = Instant::now();
+        let mut pending = PendingRequests::new(TTL);
+        pending.insert(ip("10.0.0.1"), REQUESTER, now);
+        assert_eq!(pending.len(), 1);
+        // Asked by someone else, or for another ip
+        assert!(pending.answer(&ip("10.0.0.1"), &OTHER).is_none());
+        assert!(pending.answer(&ip("10.0.0.2"), &REQUESTER).is_none());
+        let request = pending.answer(&ip("10.0.0.1"), &REQUESTER).unwrap();
+        assert_eq!(request.requester, REQUESTER);
+        assert_eq!(request.at, now);
+        assert!(pending.is_empty());
+        assert!(pending.answer(&ip("10.0.0.1"), &REQUESTER).is_none());
+    }
+
+    #[test]
+    fn broadcast_answers_any() {
+        let now = Instant::now();
+        let mut pending = PendingRequests::new(TTL);
+        pending.insert(ip("10.0.0.1"), REQUESTER, now);
+        pending.insert(ip("10.0.0.1"), OTHER, now);
+        assert_eq!(pending.len(), 2);
+        let request = pending.answer(&ip("10.0.0.1"), &MacAddr::BROADCAST);
+        assert_eq!(request.unwrap().requester, REQUESTER);
+        assert_eq!(pending.len(), 1);
+        assert!(!pending.is_empty());
+    }
+
+    #[test]
+    fn expired_after_ttl() {
+        let start = Instant::now();
+        let mut pending = PendingRequests::new(TTL);
+        pending.insert(ip("10.0.0.1"), REQUESTER, start);
+        pending.insert(ip("10.0.0.2"), REQUESTER, start + Duration::from_secs(2));
+        pending.expire(start + TTL);
+        assert_eq!(pending.len(), 2);
+        pending.expire(start + TTL + Duration::from_millis(1));
+        assert_eq!(pending.len(), 1);
+        assert!(pending.answer(&ip("10.0.0.1"), &REQUESTER).is_none());
+        assert!(pending.answer(&ip("10.0.0.2"), &REQUESTER).is_some());
+    }
+}
@@ -0,0 +1,264 @@
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvTimeoutError, SendError, TryRecvError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_CAPACITY: usize = 10000;
+pub const DEFAULT_SAMPLE: u64 = 10;
+
+/// What to do with an item sent while the queue is full
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    // Make room by dropping the item waiting the longest
+    #[default]
+    DropOldest,
+    // Drop the item being sent
+    DropNewest,
+    // Keep one item out of `sample`, dropping the oldest for it, drop the others
+    Sample,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    // Items sent while full, to pick the samples
+    overflowed: u64,
+    dropped: u64,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+    sample: u64,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // The state stays consistent even if a holder panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/**
+Queue holding at most `capacity` items, sending never blocks: once full,
+items are dropped according to the overflow policy and counted.
+It otherwise behaves like `mpsc::channel`, the receiver is disconnected
+when every sender is dropped and the other way around.
+*/
+pub fn bounded<T>(
+    capacity: usize,
+    overflow: Overflow,
+    sample: u64,
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            senders: 1,
+            receiver: true,
+            overflowed: 0,
+            dropped: 0,
+        }),
+        ready: Condvar::new(),
+        capacity: capacity.max(1),
+        overflow,
+        sample: sample.max(1),
+    });
+    (
+        BoundedSender {
+            shared: shared.clone(),
+        },
+        BoundedReceiver { shared },
+    )
+}
+
+pub struct BoundedSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BoundedSender<T> {
+    /// Queues the item, fails only when the receiver is gone
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+        if !state.receiver {
+            return Err(SendError(item));
+        }
+        if state.items.len() >= shared.capacity {
+            state.overflowed += 1;
+            let keep = match shared.overflow {
+                Overflow::DropOldest => true,
+                Overflow::DropNewest => false,
+                Overflow::Sample => state.overflowed.is_multiple_of(shared.sample),
+            };
+            state.dropped += 1;
+            if !keep {
+                return Ok(());
+            }
+            state.items.pop_front();
+        }
+        state.items.push_back(item);
+        drop(state);
+        shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> BoundedSender<T> {
+        self.shared.lock().senders += 1;
+        BoundedSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.ready.notify_all();
+    }
+}
+
+pub struct BoundedReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BoundedReceiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match state.items.pop_front() {
+            Some(item) => Ok(item),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Items already queued, without waiting
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.try_recv().ok())
+    }
+
+    /// Items dropped because the queue was full, since it was created
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(overflow: Overflow, sample: u64) -> BoundedReceiver<u32> {
+        let (tx, rx) = bounded(3, overflow, sample);
+        for i in 0..8 {
+            tx.send(i).unwrap();
+        }
+        rx
+    }
+
+    #[test]
+    fn drop_oldest_keeps_newest() {
+        let rx = fill(Overflow::DropOldest, DEFAULT_SAMPLE);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [5, 6, 7]);
+        assert_eq!(rx.dropped(), 5);
+    }
+
+    #[test]
+    fn drop_newest_keeps_oldest() {
+        let rx = fill(Overflow::DropNewest, DEFAULT_SAMPLE);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(rx.dropped(), 5);
+    }
+
+    #[test]
+    fn sample_keeps_one_out_of_sample() {
+        let rx = fill(Overflow::Sample, 2);
+        // 3 to 7 overflow, the 2nd and 4th of them (4 and 6) replace the oldest
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [2, 4, 6]);
+        assert_eq!(rx.dropped(), 5);
+    }
+
+    #[test]
+    fn no_drop_below_capacity() {
+        let (tx, rx) = bounded(3, Overflow::DropOldest, DEFAULT_SAMPLE);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.dropped(), 0);
+    }
+
+    #[test]
+    fn zero_capacity_holds_one() {
+        let (tx, rx) = bounded(0, Overflow::DropOldest, 0);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn disconnects() {
+        let (tx, rx) = bounded(3, Overflow::DropOldest, DEFAULT_SAMPLE);
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        let (tx, rx) = bounded(3, Overflow::DropOldest, DEFAULT_SAMPLE);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        drop(rx);
+        assert!(tx.send(1).is_err());
+    }
+}
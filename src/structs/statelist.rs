@@ -1,43 +1,67 @@
+use std::collections::VecDeque;
 use tui::widgets::ListState;
 
-use super::net::{Device, MacAddr};
-
+/// Items shown in a list, the oldest dropped once `capacity` is reached
 pub struct StateList<T> {
-    pub items: Vec<T>,
+    pub items: VecDeque<T>,
     pub state: ListState,
+    capacity: usize,
+    // Items dropped so far, the position of an item since the start is offset by it
+    evicted: usize,
 }
 
-impl StateList<Device> {
-    pub fn get_by_mac(&self, searched: &MacAddr) -> Option<&Device> {
-        for device in self.items.iter() {
-            if device.mac == *searched {
-                return Some(device);
-            }
-        }
-        None
-    }
-
-    pub fn get(&self, searched: &Device) -> Option<&Device> {
-        for dev in self.items.iter() {
-            if *dev == *searched {
-                return Some(&dev);
-            }
-        }
-        None
+impl<T> Default for StateList<T> {
+    fn default() -> StateList<T> {
+        StateList::new()
     }
 }
 
 impl<T> StateList<T> {
     pub fn new() -> StateList<T> {
+        StateList::with_capacity(usize::MAX)
+    }
+
+    pub fn with_capacity(capacity: usize) -> StateList<T> {
         StateList {
-            items: vec![],
+            items: VecDeque::new(),
             state: ListState::default(),
+            capacity: capacity.max(1),
+            evicted: 0,
         }
     }
 
+    /// Appends the item, dropping the oldest one when full
+    pub fn push(&mut self, item: T) {
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+            self.evicted += 1;
+            // The selection stays on the same item, if it is still there
+            match self.state.selected() {
+                Some(0) => self.state.select(None),
+                Some(i) => self.state.select(Some(i - 1)),
+                None => (),
+            }
+        }
+        self.items.push_back(item);
+    }
+
+    /// Items with their position since the first one ever pushed
+    pub fn numbered(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (self.evicted + i, item))
+    }
+
+    /// Item at a position since the first one ever pushed, None once dropped
+    pub fn get_mut(&mut self, number: usize) -> Option<&mut T> {
+        let i = number.checked_sub(self.evicted)?;
+        self.items.get_mut(i)
+    }
+
     pub fn next(&mut self) {
         // Making sure there is at least one item
-        if self.items.len() == 0 {
+        if self.items.is_empty() {
             return;
         }
 
@@ -55,12 +79,12 @@ impl<T> StateList<T> {
     }
 
     pub fn prev(&mut self) {
-        if self.items.len() == 0 {
+        if self.items.is_empty() {
             return;
         }
 
         let i = match self.state.selected() {
-            Some(i) => i.checked_sub(1).unwrap_or(0),
+            Some(i) => i.saturating_sub(1),
             None => 0,
         };
         self.state.select(Some(i));
@@ -74,6 +98,10 @@ impl<T> StateList<T> {
         }
     }
 
+    pub fn selected(&self) -> Option<&T> {
+        self.state.selected().and_then(|i| self.items.get(i))
+    }
+
     pub fn unselect(&mut self) {
         self.state.select(None);
     }
@@ -0,0 +1,148 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Default)]
+pub struct DeviceStats {
+    pub last_seen: Option<Instant>,
+    // Not seen for a while, reported as gone
+    pub gone: bool,
+    pub gratuitous: usize,
+    pub unsolicited: usize,
+    last_unsolicited_alert: Option<Instant>,
+    pub last_latency: Option<Duration>,
+    total_latency: Duration,
+    answered: u32,
+    // Recent gratuitous ARP timestamps, used for storm detection
+    gratuitous_times: VecDeque<Instant>,
+    storming: bool,
+}
+
+impl DeviceStats {
+    /// Records a gratuitous ARP, returns true when the host starts storming:
+    /// more than `threshold` gratuitous ARPs over the last `window`.
+    pub fn record_gratuitous(&mut self, at: Instant, window: Duration, threshold: usize) -> bool {
+        self.gratuitous += 1;
+        self.gratuitous_times.push_back(at);
+        while let Some(oldest) = self.gratuitous_times.front() {
+            if at.duration_since(*oldest) <= window {
+                break;
+            }
+            self.gratuitous_times.pop_front();
+        }
+
+        let was_storming = self.storming;
+        self.storming = self.gratuitous_times.len() > threshold;
+        self.storming && !was_storming
+    }
+
+    pub fn is_storming(&self) -> bool {
+        self.storming
+    }
+
+    /// Records an unsolicited reply, returns true when it should be alerted on,
+    /// at most once per `interval`
+    pub fn record_unsolicited(&mut self, at: Instant, interval: Duration) -> bool {
+        self.unsolicited += 1;
+        match self.last_unsolicited_alert {
+            Some(last) if at.saturating_duration_since(last) < interval => false,
+            _ => {
+                self.last_unsolicited_alert = Some(at);
+                true
+            }
+        }
+    }
+
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.answered += 1;
+        self.last_latency = Some(latency);
+        self.total_latency += latency;
+    }
+
+    pub fn avg_latency(&self) -> Option<Duration> {
+        if self.answered == 0 {
+            return None;
+        }
+        Some(self.total_latency / self.answered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::{GARP_STORM_THRESHOLD, GARP_STORM_WINDOW};
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn unsolicited_rate_limited() {
+        let start = Instant::now();
+        let mut stats = DeviceStats::default();
+        assert!(stats.record_unsolicited(start, INTERVAL));
+        assert!(!stats.record_unsolicited(start + Duration::from_secs(1), INTERVAL));
+        assert!(!stats.record_unsolicited(start + INTERVAL - Duration::from_millis(1), INTERVAL));
+        assert!(stats.record_unsolicited(start + INTERVAL, INTERVAL));
+        // Counted even when not alerted on
+        assert_eq!(stats.unsolicited, 4);
+    }
+
+    #[test]
+    fn latency_average() {
+        let mut stats = DeviceStats::default();
+        assert_eq!(stats.avg_latency(), None);
+        stats.record_latency(Duration::from_millis(10));
+        stats.record_latency(Duration::from_millis(30));
+        assert_eq!(stats.last_latency, Some(Duration::from_millis(30)));
+        assert_eq!(stats.avg_latency(), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn gratuitous_storm_inside_window() {
+        let start = Instant::now();
+        let step = GARP_STORM_WINDOW / (GARP_STORM_THRESHOLD as u32 + 1);
+        let mut stats = DeviceStats::default();
+        for i in 0..GARP_STORM_THRESHOLD {
+            let at = start + step * i as u32;
+            assert!(!stats.record_gratuitous(at, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        }
+        assert!(!stats.is_storming());
+        let at = start + step * GARP_STORM_THRESHOLD as u32;
+        assert!(stats.record_gratuitous(at, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        assert!(stats.is_storming());
+        // Reported when it starts, not for every packet of the storm
+        assert!(!stats.record_gratuitous(at, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        assert_eq!(stats.gratuitous, GARP_STORM_THRESHOLD + 2);
+    }
+
+    #[test]
+    fn gratuitous_spread_outside_window() {
+        let start = Instant::now();
+        // As many announcements, spread so that the window never holds more than the threshold
+        let step = GARP_STORM_WINDOW / GARP_STORM_THRESHOLD as u32 + Duration::from_millis(1);
+        let mut stats = DeviceStats::default();
+        for i in 0..GARP_STORM_THRESHOLD * 3 {
+            let at = start + step * i as u32;
+            assert!(!stats.record_gratuitous(at, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        }
+        assert!(!stats.is_storming());
+    }
+
+    #[test]
+    fn gratuitous_storm_ends_and_restarts() {
+        let start = Instant::now();
+        let mut stats = DeviceStats::default();
+        for _ in 0..GARP_STORM_THRESHOLD {
+            stats.record_gratuitous(start, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD);
+        }
+        assert!(stats.record_gratuitous(start, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        // Quiet for longer than the window, the next one is alone in it
+        let later = start + GARP_STORM_WINDOW + Duration::from_secs(1);
+        assert!(!stats.record_gratuitous(later, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+        assert!(!stats.is_storming());
+        for _ in 0..GARP_STORM_THRESHOLD - 1 {
+            stats.record_gratuitous(later, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD);
+        }
+        assert!(stats.record_gratuitous(later, GARP_STORM_WINDOW, GARP_STORM_THRESHOLD));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use super::net::{Device, DeviceKey, IpAddr, MacAddr};
+
+/**
+Device table with hash indexes, devices are never removed so their index
+is stable. Several devices can claim the same ip, and the same mac can be
+seen on several interfaces.
+*/
+#[derive(Debug, Default)]
+pub struct DeviceStore {
+    devices: Vec<Device>,
+    by_key: HashMap<DeviceKey, usize>,
+    by_ip: HashMap<IpAddr, Vec<usize>>,
+    by_mac: HashMap<MacAddr, Vec<usize>>,
+    // Devices of each interface, in insertion order
+    by_iface: HashMap<String, Vec<usize>>,
+}
+
+impl DeviceStore {
+    pub fn new() -> DeviceStore {
+        DeviceStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Every device, in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = &Device> {
+        self.devices.iter()
+    }
+
+    pub fn get(&self, key: &DeviceKey) -> Option<&Device> {
+        self.by_key.get(key).map(|i| &self.devices[*i])
+    }
+
+    /// First device seen with that ip on the interface
+    pub fn get_by_ip(&self, ip: &IpAddr, iface: &str) -> Option<&Device> {
+        self.find_by_ip(ip).find(|dev| dev.iface == iface)
+    }
+
+    /// The devices using that ip, on every interface
+    pub fn find_by_ip(&self, ip: &IpAddr) -> impl Iterator<Item = &Device> {
+        self.by_ip
+            .get(ip)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.devices[*i])
+    }
+
+    /// The devices using that mac, on every interface
+    pub fn find_by_mac(&self, mac: &MacAddr) -> impl Iterator<Item = &Device> {
+        self.by_mac
+            .get(mac)
+            .into_iter()
+            .flatten()
+            .map(|i| &self.devices[*i])
+    }
+
+    /// Adds the device, or moves it to its new ip. Returns the device before the move.
+    pub fn upsert(&mut self, dev: Device) -> Option<Device> {
+        let key = dev.key();
+        let Some(&i) = self.by_key.get(&key) else {
+            let i = self.devices.len();
+            self.by_key.insert(key, i);
+            self.by_ip.entry(dev.ip).or_default().push(i);
+            self.by_mac.entry(dev.mac).or_default().push(i);
+            self.by_iface.entry(dev.iface.clone()).or_default().push(i);
+            self.devices.push(dev);
+            return None;
+        };
+        if self.devices[i].ip == dev.ip {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.devices[i], dev);
+        if let Some(indexes) = self.by_ip.get_mut(&previous.ip) {
+            indexes.retain(|index| *index != i);
+        }
+        let ip = self.devices[i].ip;
+        self.by_ip.entry(ip).or_default().push(i);
+        Some(previous)
+    }
+
+    /// Number of devices of the interface, or of every interface
+    pub fn count(&self, iface: Option<&str>) -> usize {
+        match iface {
+            Some(iface) => self.by_iface.get(iface).map(Vec::len).unwrap_or(0),
+            None => self.devices.len(),
+        }
+    }
+
+    /// The nth device of the interface, or of every interface
+    pub fn nth(&self, iface: Option<&str>, n: usize) -> Option<&Device> {
+        let i = match iface {
+            Some(iface) => *self.by_iface.get(iface)?.get(n)?,
+            None => n,
+        };
+        self.devices.get(i)
+    }
+
+    /// At most `len` devices of the interface, or of every interface, from the `start`th
+    pub fn window(&self, iface: Option<&str>, start: usize, len: usize) -> Vec<&Device> {
+        (start..start + len)
+            .map_while(|n| self.nth(iface, n))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::device;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn mac(s: &str) -> MacAddr {
+        s.parse().unwrap()
+    }
+
+    /// Every device is found through each index, and nothing else is
+    fn assert_consistent(store: &DeviceStore) {
+        for dev in store.iter() {
+            assert_eq!(store.get(&dev.key()), Some(dev));
+            assert!(store.find_by_ip(&dev.ip).any(|found| found == dev));
+            assert!(store.find_by_mac(&dev.mac).any(|found| found == dev));
+        }
+        for (ip, indexes) in store.by_ip.iter() {
+            assert!(indexes.iter().all(|i| store.devices[*i].ip == *ip));
+        }
+        for (mac, indexes) in store.by_mac.iter() {
+            assert!(indexes.iter().all(|i| store.devices[*i].mac == *mac));
+        }
+        let indexed: usize = store.by_iface.values().map(Vec::len).sum();
+        assert_eq!(indexed, store.len());
+    }
+
+    #[test]
+    fn upsert_new_and_same() {
+        let mut store = DeviceStore::new();
+        assert!(store.is_empty());
+        assert_eq!(
+            store.upsert(device("02:00:00:00:00:01", "10.0.0.1", "eth0")),
+            None
+        );
+        assert_eq!(
+            store.upsert(device("02:00:00:00:00:01", "10.0.0.1", "eth0")),
+            None
+        );
+        assert_eq!(store.len(), 1);
+        // Same mac on another interface is another device
+        assert_eq!(
+            store.upsert(device("02:00:00:00:00:01", "10.1.0.1", "eth1")),
+            None
+        );
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.find_by_mac(&mac("02:00:00:00:00:01")).count(), 2);
+        assert_consistent(&store);
+    }
+
+    #[test]
+    fn upsert_moves_ip_index() {
+        let mut store = DeviceStore::new();
+        store.upsert(device("02:00:00:00:00:01", "10.0.0.1", "eth0"));
+        store.upsert(device("02:00:00:00:00:02", "10.0.0.1", "eth0"));
+        let previous = store.upsert(device("02:00:00:00:00:01", "10.0.0.9", "eth0"));
+        assert_eq!(
+            previous,
+            Some(device("02:00:00:00:00:01", "10.0.0.1", "eth0"))
+        );
+        assert_eq!(store.len(), 2);
+        // Only the device still claiming the old ip is found through it
+        let at_old: Vec<&Device> = store.find_by_ip(&ip("10.0.0.1")).collect();
+        assert_eq!(at_old, [&device("02:00:00:00:00:02", "10.0.0.1", "eth0")]);
+        assert_eq!(
+            store.get_by_ip(&ip("10.0.0.9"), "eth0"),
+            Some(&device("02:00:00:00:00:01", "10.0.0.9", "eth0"))
+        );
+        assert_eq!(store.get_by_ip(&ip("10.0.0.9"), "eth1"), None);
+        // The order of the table doesn't change
+        assert_eq!(store.nth(None, 0).unwrap().ip, ip("10.0.0.9"));
+        assert_consistent(&store);
+
+        // And back
+        store.upsert(device("02:00:00:00:00:01", "10.0.0.1", "eth0"));
+        assert_eq!(store.find_by_ip(&ip("10.0.0.1")).count(), 2);
+        assert_eq!(store.find_by_ip(&ip("10.0.0.9")).count(), 0);
+        assert_consistent(&store);
+    }
+
+    #[test]
+    fn per_iface_views() {
+        let mut store = DeviceStore::new();
+        for (i, iface) in ["eth0", "eth1", "eth0", "eth0", "eth1"].iter().enumerate() {
+            store.upsert(device(
+                &format!("02:00:00:00:00:0{}", i),
+                &format!("10.0.0.{}", i),
+                iface,
+            ));
+        }
+        assert_eq!(store.count(None), 5);
+        assert_eq!(store.count(Some("eth0")), 3);
+        assert_eq!(store.count(Some("eth1")), 2);
+        assert_eq!(store.count(Some("wlan0")), 0);
+
+        assert_eq!(store.nth(Some("eth1"), 1).unwrap().ip, ip("10.0.0.4"));
+        assert_eq!(store.nth(Some("eth1"), 2), None);
+        assert_eq!(store.nth(Some("wlan0"), 0), None);
+        assert_eq!(store.nth(None, 4).unwrap().ip, ip("10.0.0.4"));
+        assert_eq!(store.nth(None, 5), None);
+
+        let ips =
+            |window: Vec<&Device>| -> Vec<IpAddr> { window.iter().map(|dev| dev.ip).collect() };
+        assert_eq!(
+            ips(store.window(Some("eth0"), 1, 5)),
+            [ip("10.0.0.2"), ip("10.0.0.3")]
+        );
+        assert_eq!(ips(store.window(None, 3, 1)), [ip("10.0.0.3")]);
+        assert!(store.window(Some("eth1"), 2, 3).is_empty());
+        assert!(store.window(None, 0, 0).is_empty());
+        assert_consistent(&store);
+    }
+}
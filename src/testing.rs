@@ -0,0 +1,48 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::Config,
+    sinks::Sinks,
+    structs::{
+        event::{Event, EventKind},
+        net::Device,
+    },
+    App,
+};
+
+// 2022-08-01T10:00:00Z, the time of the test events
+pub const TIME: u64 = 1_659_348_000;
+
+pub fn device(mac: &str, ip: &str, iface: &str) -> Device {
+    Device {
+        mac: mac.parse().unwrap(),
+        ip: ip.parse().unwrap(),
+        iface: iface.to_owned(),
+    }
+}
+
+/// Event happening at TIME
+pub fn event(kind: EventKind) -> Event {
+    Event {
+        time: SystemTime::UNIX_EPOCH + Duration::from_secs(TIME),
+        kind,
+    }
+}
+
+/// App watching eth0, without sinks
+pub fn app(config: Config) -> App {
+    App::new(&["eth0".to_owned()], config, Sinks::default()).0
+}
+
+/// Empty directory of its own for a test, under the temporary directory
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("arp-watch-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
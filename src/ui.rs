@@ -1,7 +1,18 @@
-use std::{error::Error, io, time::Duration};
+use signal_hook::consts::SIGHUP;
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use crossterm::{
-    event::{self, poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -11,17 +22,19 @@ use tui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style},
     text::{Span, Text},
-    widgets::{Block, BorderType, Borders, List, ListItem, Paragraph},
+    widgets::{Block, BorderType, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
 
 use crate::{
-    sniff::local_mac,
-    structs::{
-        arp::ARPOperation,
-        net::{Device, MacAddr},
+    config::Theme,
+    probe::{probe, ProbeReport},
+    sniff::{
+        clamp_sweep, find_interface, interface_ranges, local_mac, ScanCommand, ScanProgress,
+        MAX_SWEEP_ADDRESSES,
     },
-    App,
+    structs::net::{IpRange, MacAddr},
+    App, InputMode, Popup,
 };
 
 pub fn start_ui(app: App) -> Result<(), Box<dyn Error>> {
@@ -40,92 +53,408 @@ pub fn start_ui(app: App) -> Result<(), Box<dyn Error>> {
         DisableMouseCapture
     )?;
 
-    if let Err(e) = res {
-        error!("UI Crashed {}", e);
-    }
-    Ok(())
+    res.map_err(|e| format!("UI crashed: {}", e).into())
 }
 
+// Redraws happen at most once per FRAME_INTERVAL, and at least once per IDLE_REDRAW
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+const IDLE_REDRAW: Duration = Duration::from_secs(1);
+// Longest time spent on queued packets before looking at input again
+const DRAIN_BUDGET: Duration = Duration::from_millis(30);
+
 fn run_app<B: Backend>(term: &mut Terminal<B>, mut app: App) -> Result<(), Box<dyn Error>> {
-    let local_mac = local_mac()?;
+    let mut local_macs = HashMap::new();
+    for iface in app.ifaces.iter() {
+        local_macs.insert(iface.clone(), local_mac(&find_interface(iface)?)?);
+    }
+    let sighup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, sighup.clone())?;
+    let mut last_draw: Option<Instant> = None;
+    let mut dirty = true;
     loop {
-        if let Ok(packet) = app.rx.try_recv() {
-            match packet.operation {
-                ARPOperation::Request => {}
-                ARPOperation::Reply => {
-                    let dev = Device {
-                        mac: packet.sender_mac,
-                        ip: packet.sender_ip,
+        // Input first, so that keys are handled even while packets flood in
+        while poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) => {
+                    let quit = match app.mode {
+                        InputMode::NormalMode => handle_normal_key(&mut app, key),
+                        InputMode::EditMode => handle_edit_key(&mut app, key),
                     };
-                    if let Some(already_existing) = app.list.get_by_mac(&dev.mac) {
-                        // Command::new("sh")
-                        //     .args([
-                        //         "-c",
-                        //         "notify-send",
-                        //         "ARP Watch",
-                        //         format!(
-                        //             "[{}] @ {} -> [{}] {}",
-                        //             already_existing.mac, already_existing.ip, dev.mac, dev.ip
-                        //         )
-                        //         .as_str(),
-                        //     ])
-                        //     .exec();
-                        if already_existing.ip != dev.ip {
-                            app.changement_list
-                                .items
-                                .push((already_existing.clone(), dev));
-                        }
-                    } else {
-                        app.list.items.push(dev);
+                    if quit {
+                        return Ok(());
                     }
                 }
+                Event::Resize(_, _) => (),
+                _ => continue,
             }
-            app.arp_frame_counter += 1;
+            dirty = true;
+        }
+        if sighup.swap(false, Ordering::Relaxed) {
+            reload_config(&mut app);
+            dirty = true;
         }
 
-        term.draw(|f| ui(f, &mut app, local_mac.clone()))?;
-        if poll(Duration::from_millis(100)).unwrap() {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => return Ok(()),
-                    _ => (),
+        // Everything queued is processed before drawing, unless it takes too long
+        let deadline = Instant::now() + DRAIN_BUDGET;
+        let mut backlog = false;
+        while let Ok(packet) = app.rx.try_recv() {
+            app.handle_packet(packet);
+            dirty = true;
+            if Instant::now() >= deadline {
+                backlog = true;
+                break;
+            }
+        }
+        app.tick(Instant::now());
+        app.poll_control();
+        if app.poll_scans() | app.poll_kernel() {
+            dirty = true;
+        }
+        let probes: Vec<Result<ProbeReport, String>> = app.probe_rx.try_iter().collect();
+        for probe in probes {
+            match probe {
+                Ok(report) => {
+                    app.status = format!("Probe of {} done", report.range);
+                    app.probe_list.push(report);
                 }
+                Err(e) => app.status = e,
+            }
+            dirty = true;
+        }
+
+        let since_draw = last_draw.map(|last| last.elapsed());
+        let due = match since_draw {
+            Some(since_draw) => {
+                (dirty && since_draw >= FRAME_INTERVAL) || since_draw >= IDLE_REDRAW
             }
+            None => true,
+        };
+        if due {
+            term.draw(|f| ui(f, &mut app, &local_macs))?;
+            last_draw = Some(Instant::now());
+            dirty = false;
         }
+
+        // Sleeps until input or the next frame, packets queue up in the meantime
+        let wait = if backlog {
+            Duration::ZERO
+        } else if dirty {
+            FRAME_INTERVAL.saturating_sub(last_draw.map(|last| last.elapsed()).unwrap_or_default())
+        } else {
+            FRAME_INTERVAL
+        };
+        poll(wait)?;
     }
 }
 
-fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App, mac: MacAddr) {
+/// Returns true when the app should exit
+fn handle_normal_key(app: &mut App, key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') => return true,
+        KeyCode::Char('p') => app.open_popup(Popup::Probe),
+        KeyCode::Char('i') => app.next_view(),
+        KeyCode::Char('r') => reload_config(app),
+        KeyCode::Char('e') => reprobe_selected(app),
+        KeyCode::Char('s') => {
+            app.open_popup(Popup::Rescan);
+            // The network of the selected device, as much of it as a sweep covers
+            if let Some(dev) = app.selected_device() {
+                let network = find_interface(&dev.iface)
+                    .map(|interface| interface_ranges(&interface))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|network| network.contains(&dev.ip));
+                if let Some(network) = network {
+                    app.input = clamp_sweep(&network, &dev.ip).to_string();
+                }
+            }
+        }
+        KeyCode::Char('S') => full_sweep(app),
+        KeyCode::Down | KeyCode::Char('j') => app.next_device(),
+        KeyCode::Up | KeyCode::Char('k') => app.prev_device(),
+        _ => (),
+    }
+    false
+}
+
+fn handle_edit_key(app: &mut App, key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Esc => app.close_popup(),
+        KeyCode::Backspace => {
+            app.input.pop();
+        }
+        KeyCode::Char(c) => app.input.push(c),
+        KeyCode::Enter => {
+            match app.popup {
+                Popup::Probe => start_probe(app),
+                Popup::Rescan => start_rescan(app),
+                _ => (),
+            }
+            app.close_popup();
+        }
+        _ => (),
+    }
+    false
+}
+
+fn reload_config(app: &mut App) {
+    app.status = match app.reload_config() {
+        Ok(pending) if pending.is_empty() => "Config reloaded".to_owned(),
+        Ok(pending) => format!(
+            "Config reloaded, restart to apply the changes of [{}]",
+            pending.join(", ")
+        ),
+        Err(e) => {
+            error!("{}", e);
+            format!("Config not reloaded: {}", e)
+        }
+    };
+}
+
+fn start_probe(app: &mut App) {
+    let range: IpRange = match app.input.parse() {
+        Ok(range) => range,
+        Err(e) => {
+            app.status = format!("Invalid probe target {}: {}", app.input, e);
+            return;
+        }
+    };
+    // Probes go out of the displayed interface, or the first one in combined view
+    let iface = match app.viewed_iface().or_else(|| app.ifaces.first()) {
+        Some(iface) => iface.clone(),
+        None => return,
+    };
+    app.status = format!("Probing {} on {}", range, iface);
+    let probe_tx = app.probe_tx.clone();
+    thread::spawn(move || {
+        let res = probe(&iface, &range).map_err(|e| {
+            error!("Probe of {} failed: {}", range, e);
+            format!("Probe of {} failed: {}", range, e)
+        });
+        let _ = probe_tx.send(res);
+    });
+}
+
+/// Asks the scanner of the selected device for a fresh request to it
+fn reprobe_selected(app: &mut App) {
+    let Some(dev) = app.selected_device() else {
+        return;
+    };
+    let (iface, ip) = (dev.iface.clone(), dev.ip);
+    app.status = match app.scan_command(Some(&iface), ScanCommand::Probe(ip)) {
+        0 => format!("No scanner on {}", iface),
+        _ => format!("Probing {} on {}", ip, iface),
+    };
+}
+
+fn start_rescan(app: &mut App) {
+    let range: IpRange = match app.input.parse() {
+        Ok(range) => range,
+        Err(e) => {
+            app.status = format!("Invalid range {}: {}", app.input, e);
+            return;
+        }
+    };
+    // Like probes, out of the displayed interface or the first one in combined view
+    let iface = match app.viewed_iface().or_else(|| app.ifaces.first()) {
+        Some(iface) => iface.clone(),
+        None => return,
+    };
+    // Sweeps stay on the segment, where replies can come from
+    let networks = find_interface(&iface)
+        .map(|interface| interface_ranges(&interface))
+        .unwrap_or_default();
+    if !networks.iter().any(|network| network.includes(&range)) {
+        app.status = format!("{} is outside the networks of {}", range, iface);
+        return;
+    }
+    if range.size() > MAX_SWEEP_ADDRESSES {
+        app.status = format!(
+            "{} is too large, at most {} addresses",
+            range, MAX_SWEEP_ADDRESSES
+        );
+        return;
+    }
+    app.status = match app.scan_command(Some(&iface), ScanCommand::Sweep(range.clone())) {
+        0 => format!("No scanner on {}", iface),
+        _ => format!("Rescanning {} on {}", range, iface),
+    };
+}
+
+/// Starts the sweep of the displayed interfaces over, without waiting for the interval
+fn full_sweep(app: &mut App) {
+    let iface = app.viewed_iface().cloned();
+    app.status = match app.scan_command(iface.as_deref(), ScanCommand::Rescan) {
+        0 => "No scanner running".to_owned(),
+        count => format!("Full sweep started on {} interface(s)", count),
+    };
+}
+
+fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App, local_macs: &HashMap<String, MacAddr>) {
     let header_chunk = Rect::new(0, 0, f.size().width, 3);
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-        .split(Rect::new(0, 3, f.size().width, f.size().height - 3));
-    f.render_widget(header(app.arp_frame_counter, mac), header_chunk);
+        .constraints(
+            [
+                Constraint::Percentage(40),
+                Constraint::Percentage(20),
+                Constraint::Percentage(20),
+                Constraint::Percentage(20),
+            ]
+            .as_ref(),
+        )
+        .split(Rect::new(
+            0,
+            3,
+            f.size().width,
+            f.size().height.saturating_sub(4),
+        ));
+    let status_chunk = Rect::new(0, f.size().height.saturating_sub(1), f.size().width, 1);
+    let device_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .split(chunks[0]);
+    let view = app
+        .ifaces
+        .iter()
+        .filter(|iface| app.is_viewed(iface))
+        .map(|iface| match local_macs.get(iface) {
+            Some(mac) => format!("{} [{}]", iface, mac),
+            None => iface.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    f.render_widget(
+        header(app.arp_frame_counter, app.rx.dropped(), view, &app.status),
+        header_chunk,
+    );
+    f.render_widget(status_bar(app), status_chunk);
+    let theme = app.config.ui.theme;
+    // Only the rows that fit are built, borders take two lines
+    let rows = |area: Rect| area.height.saturating_sub(2) as usize;
+
+    let height = rows(device_chunks[0]);
+    app.scroll_to_selected(height);
+    let mut device_state = ListState::default();
+    device_state.select(app.selected.map(|selected| selected - app.scroll()));
     f.render_stateful_widget(
-        render_list(app.list.items.clone(), |item| item.to_string()),
-        chunks[0],
-        &mut app.list.state,
+        render_list(app.visible_devices(height), theme, |item| {
+            match app.config.alias(&item.mac) {
+                Some(alias) => format!("{} ({})", item, alias),
+                None => item.to_string(),
+            }
+        }),
+        device_chunks[0],
+        &mut device_state,
+    );
+    f.render_widget(detail(app), device_chunks[1]);
+    let changes = newest(
+        app.changement_list
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, dev))| app.is_viewed(&dev.iface)),
+        rows(chunks[1]),
     );
     f.render_stateful_widget(
-        render_list(app.changement_list.items.clone(), |item| {
+        render_list(changes.iter().map(|(_, item)| item), theme, |item| {
             format!("{} -> {}", item.0, item.1)
         }),
         chunks[1],
-        &mut app.changement_list.state,
+        &mut window_state(&changes, app.changement_list.state.selected()),
+    );
+    let alerts = newest(
+        app.alerts
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, alert)| app.is_viewed(&alert.device.iface)),
+        rows(chunks[2]),
     );
+    f.render_stateful_widget(
+        render_list(
+            alerts.iter().map(|(_, item)| item),
+            theme,
+            |item| match item.acked {
+                true => format!("{} (acked)", item),
+                false => item.to_string(),
+            },
+        ),
+        chunks[2],
+        &mut window_state(&alerts, app.alerts.state.selected()),
+    );
+    let probes = newest(app.probe_list.items.iter().enumerate(), rows(chunks[3]));
+    f.render_stateful_widget(
+        render_list(probes.iter().map(|(_, item)| item), theme, |item| {
+            item.to_string()
+        }),
+        chunks[3],
+        &mut window_state(&probes, app.probe_list.state.selected()),
+    );
+
+    let title = match app.popup {
+        Popup::Probe => Some("Probe (ip, range or cidr)"),
+        Popup::Rescan => Some("Rescan (ip, range or cidr)"),
+        _ => None,
+    };
+    if let Some(title) = title {
+        let area = centered_rect(50, 3, f.size());
+        f.render_widget(Clear, area);
+        f.render_widget(input_box(title, &app.input), area);
+    }
+}
+
+/// Selection within a window of numbered items, none when the selected item isn't shown
+fn window_state<T>(window: &[(usize, T)], selected: Option<usize>) -> ListState {
+    let mut state = ListState::default();
+    state.select(selected.and_then(|selected| window.iter().position(|(i, _)| *i == selected)));
+    state
+}
+
+/// Last `rows` items, oldest first, so that new ones always show at the bottom
+fn newest<T>(items: impl DoubleEndedIterator<Item = T>, rows: usize) -> Vec<T> {
+    let mut newest: Vec<T> = items.rev().take(rows).collect();
+    newest.reverse();
+    newest
+}
+
+fn centered_rect(percent_x: u16, height: u16, r: Rect) -> Rect {
+    let width = r.width * percent_x / 100;
+    Rect::new(
+        r.x + (r.width - width) / 2,
+        r.y + r.height.saturating_sub(height) / 2,
+        width,
+        height.min(r.height),
+    )
+}
+
+fn input_box(title: &'static str, input: &str) -> Paragraph<'static> {
+    Paragraph::new(Text::raw(input.to_owned())).block(
+        Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded),
+    )
+}
+
+fn highlight_style(theme: Theme) -> Style {
+    match theme {
+        Theme::Dark => Style::default().fg(Color::Black).bg(Color::White),
+        Theme::Light => Style::default().fg(Color::White).bg(Color::Black),
+    }
 }
 
-fn render_list<T, F>(list: Vec<T>, f: F) -> List<'static>
+fn render_list<T, I, F>(list: I, theme: Theme, f: F) -> List<'static>
 where
+    I: IntoIterator<Item = T>,
     F: Fn(&T) -> String,
 {
     let items = list
-        .iter()
-        .map(|item| ListItem::new(Span::raw(f(item))))
+        .into_iter()
+        .map(|item| ListItem::new(Span::raw(f(&item))))
         .collect::<Vec<ListItem>>();
     List::new(items)
-        .highlight_style(Style::default().fg(Color::Black).bg(Color::White))
+        .highlight_style(highlight_style(theme))
         .block(
             Block::default()
                 .borders(Borders::ALL)
@@ -133,10 +462,72 @@ where
         )
 }
 
-fn header(frame_count: usize, mac: MacAddr) -> Paragraph<'static> {
+fn detail(app: &App) -> Paragraph<'static> {
+    let text = match app.selected_device() {
+        Some(dev) => {
+            let mut lines = vec![
+                format!("Alias: {}", app.config.alias(&dev.mac).unwrap_or("-")),
+                format!("MAC: {}", dev.mac),
+                format!("IP: {}", dev.ip),
+                format!("Interface: {}", dev.iface),
+            ];
+            if app.kernel_rx.is_some() {
+                let cached = app.kernel.get(&(dev.iface.clone(), dev.ip));
+                lines.push(match cached {
+                    Some(neighbour)
+                        if neighbour.mac.is_some() && neighbour.mac != Some(dev.mac) =>
+                    {
+                        format!("Kernel: {} MISMATCH", neighbour)
+                    }
+                    Some(neighbour) => format!("Kernel: {}", neighbour),
+                    None => "Kernel: no entry".to_owned(),
+                });
+            }
+            if let Some(stats) = app.stats.get(&dev.key()) {
+                lines.push(format!("Gratuitous ARP: {}", stats.gratuitous));
+                if stats.is_storming() {
+                    lines.push("Gratuitous ARP storm in progress".to_owned());
+                }
+                lines.push(format!("Unsolicited replies: {}", stats.unsolicited));
+                if let (Some(last), Some(avg)) = (stats.last_latency, stats.avg_latency()) {
+                    lines.push(format!("ARP latency: {:?} (avg {:?})", last, avg));
+                }
+            }
+            lines.join("\n")
+        }
+        None => "No device selected".to_owned(),
+    };
+    Paragraph::new(Text::raw(text)).block(
+        Block::default()
+            .title("Detail")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded),
+    )
+}
+
+/// Progress of the scanners of the displayed interfaces, and the scan keys
+fn status_bar(app: &App) -> Paragraph<'static> {
+    let mut scans: Vec<String> = app
+        .scans
+        .values()
+        .filter(|progress| app.is_viewed(&progress.iface))
+        .map(ScanProgress::to_string)
+        .collect();
+    scans.sort();
+    let progress = match scans.is_empty() {
+        true => "No scan yet".to_owned(),
+        false => scans.join(" | "),
+    };
+    Paragraph::new(Text::raw(format!(
+        "{}  [e] re-probe  [s] rescan subnet  [S] full sweep",
+        progress
+    )))
+}
+
+fn header(frame_count: usize, dropped: u64, view: String, status: &str) -> Paragraph<'static> {
     Paragraph::new(Text::raw(format!(
-        "ARP Watch [{}] (Frame: {})",
-        mac, frame_count
+        "ARP Watch {} (Frame: {}, Dropped: {}) {}",
+        view, frame_count, dropped, status
     )))
     .alignment(Alignment::Center)
     .block(
@@ -145,3 +536,25 @@ fn header(frame_count: usize, mac: MacAddr) -> Paragraph<'static> {
             .border_type(tui::widgets::BorderType::Double),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_within_window() {
+        let items = ["a", "b", "c", "d", "e"];
+        let window = newest(items.iter().enumerate(), 3);
+        assert_eq!(window, [(2, &"c"), (3, &"d"), (4, &"e")]);
+        assert_eq!(window_state(&window, Some(3)).selected(), Some(1));
+        assert_eq!(window_state(&window, Some(4)).selected(), Some(2));
+        // Scrolled out of the window
+        assert_eq!(window_state(&window, Some(0)).selected(), None);
+        assert_eq!(window_state(&window, None).selected(), None);
+
+        // Filtered out items keep their position in the full list
+        let window = newest(items.iter().enumerate().filter(|(i, _)| i % 2 == 0), 2);
+        assert_eq!(window_state(&window, Some(4)).selected(), Some(1));
+        assert_eq!(window_state(&window, Some(3)).selected(), None);
+    }
+}